@@ -0,0 +1,78 @@
+use crate::ItemEntry;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// An entry whose average price moved between two snapshots.
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceChange {
+    pub old: ItemEntry,
+    pub new: ItemEntry,
+    /// Change in `avg` relative to the old value, in percent.
+    pub percent: f64,
+}
+
+/// Differences between two snapshots of the same price table, joined on
+/// [`ItemEntry::identity`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PriceDiff {
+    /// Entries only present in the new snapshot.
+    pub added: Vec<ItemEntry>,
+    /// Entries only present in the old snapshot.
+    pub removed: Vec<ItemEntry>,
+    /// Entries whose `avg` changed by more than the threshold.
+    pub changed: Vec<PriceChange>,
+}
+
+/// Compares two snapshots, reporting entries whose `avg` moved by more than
+/// `threshold_percent` in either direction.
+pub fn diff_entries(old: &[ItemEntry], new: &[ItemEntry], threshold_percent: f64) -> PriceDiff {
+    let old_by_identity: HashMap<_, &ItemEntry> = old.iter().map(|e| (e.identity(), e)).collect();
+    let new_by_identity: HashMap<_, &ItemEntry> = new.iter().map(|e| (e.identity(), e)).collect();
+
+    let mut diff = PriceDiff::default();
+    for entry in new {
+        match old_by_identity.get(&entry.identity()) {
+            None => diff.added.push(entry.clone()),
+            Some(&previous) => {
+                let percent = percent_change(previous.price.avg, entry.price.avg);
+                if percent.abs() > threshold_percent {
+                    diff.changed.push(PriceChange { old: previous.clone(), new: entry.clone(), percent });
+                }
+            }
+        }
+    }
+    diff.removed = old
+        .iter()
+        .filter(|e| !new_by_identity.contains_key(&e.identity()))
+        .cloned()
+        .collect();
+    diff
+}
+
+fn percent_change(old: f64, new: f64) -> f64 {
+    if old == new {
+        0.0
+    } else if old == 0.0 {
+        f64::INFINITY.copysign(new)
+    } else {
+        (new - old) / old * 100.0
+    }
+}
+
+pub fn print_price_diff(diff: &PriceDiff) {
+    println!("{:<8} {:<8} {:<6} {:<6} {:<12} {:>14} {:>14} {:>9}", "item_id", "quality", "level", "trait", "variant", "old avg", "new avg", "change");
+    for change in &diff.changed {
+        let e = &change.new;
+        println!(
+            "{:<8} {:<8} {:<6} {:<6} {:<12} {:>14.2} {:>14.2} {:>+8.1}%",
+            e.item_id, e.quality, e.level, e.trait_id, e.variant, change.old.price.avg, e.price.avg, change.percent
+        );
+    }
+    println!("\n{} changed, {} added, {} removed", diff.changed.len(), diff.added.len(), diff.removed.len());
+    for e in &diff.added {
+        println!("+ {} {} {} {} {} (avg {:.2})", e.item_id, e.quality, e.level, e.trait_id, e.variant, e.price.avg);
+    }
+    for e in &diff.removed {
+        println!("- {} {} {} {} {} (avg {:.2})", e.item_id, e.quality, e.level, e.trait_id, e.variant, e.price.avg);
+    }
+}
@@ -0,0 +1,371 @@
+use crate::Options;
+use flate2::read::MultiGzDecoder;
+use log::{info, warn};
+use reqwest::blocking::Client;
+use reqwest::Proxy;
+use reqwest::header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use zip::ZipArchive;
+
+/// HTTP validators from the last successful download, persisted in a small
+/// sidecar file so the next run can make a conditional request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Fingerprint of the output options of the run that saved them; see
+    /// `load_validators` in the server module.
+    #[serde(default)]
+    pub options: Option<String>,
+}
+
+impl CacheValidators {
+    /// Reads validators from `path`, treating a missing or unreadable file as
+    /// "no validators" so the download is unconditional.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|v: &HeaderValue| v.to_str().ok()).map(str::to_string);
+        CacheValidators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            options: None,
+        }
+    }
+}
+
+pub(crate) enum DownloadStatus {
+    /// A new archive was written; carries the validators to persist once it
+    /// has been processed.
+    Downloaded(CacheValidators),
+    /// The server reported the archive unchanged since `validators` were saved.
+    NotModified,
+}
+
+/// User-Agent sent with downloads unless overridden in [`Options`].
+pub const DEFAULT_USER_AGENT: &str = concat!("ttcdata/", env!("CARGO_PKG_VERSION"), " (+https://github.com/sheumais/ttcdata)");
+
+/// Builds the HTTP client used for every download in a run.
+pub(crate) fn build_client(options: &Options) -> io::Result<Client> {
+    let mut builder = Client::builder()
+        .user_agent(&options.user_agent)
+        .connect_timeout(options.timeout)
+        .timeout(options.timeout);
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(Proxy::all(proxy).map_err(invalid_proxy)?);
+    }
+    builder.build().map_err(io::Error::other)
+}
+
+pub(crate) fn invalid_proxy(e: reqwest::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid --proxy URL: {}", e))
+}
+
+/// Longest wait between two download attempts.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Wait before retry number `attempt + 1`: 1s, 2s, 4s and so on, up to
+/// [`MAX_RETRY_DELAY`].
+pub(crate) fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_secs(1u64 << attempt.min(6)).min(MAX_RETRY_DELAY)
+}
+
+/// Why one download attempt failed.
+pub(crate) enum AttemptError {
+    /// The request or the body transfer failed.
+    Http(reqwest::Error),
+    /// The file arrived empty or is not a valid ZIP.
+    BadZip(io::Error),
+    /// Writing the file failed, which another attempt would not fix.
+    Write(io::Error),
+}
+
+impl AttemptError {
+    fn is_transient(&self) -> bool {
+        match self {
+            AttemptError::Http(e) => is_retriable(e),
+            AttemptError::BadZip(_) => true,
+            AttemptError::Write(_) => false,
+        }
+    }
+}
+
+impl From<reqwest::Error> for AttemptError {
+    fn from(e: reqwest::Error) -> Self {
+        AttemptError::Http(e)
+    }
+}
+
+impl From<AttemptError> for io::Error {
+    fn from(e: AttemptError) -> Self {
+        match e {
+            AttemptError::Http(e) => io::Error::other(e),
+            AttemptError::BadZip(e) | AttemptError::Write(e) => e,
+        }
+    }
+}
+
+/// Retry bookkeeping shared by the blocking and async downloads.
+pub(crate) struct Backoff {
+    attempt: u32,
+    retries: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(retries: u32) -> Self {
+        Backoff { attempt: 0, retries }
+    }
+
+    /// How many times the download was retried so far.
+    pub(crate) fn retried(&self) -> u32 {
+        self.attempt
+    }
+
+    /// How long to wait before trying again after `error`, or the error
+    /// itself when it is not transient or the retries are used up.
+    pub(crate) fn retry(&mut self, error: AttemptError) -> io::Result<Duration> {
+        if self.attempt >= self.retries || !error.is_transient() {
+            return Err(error.into());
+        }
+        let delay = retry_delay(self.attempt);
+        self.attempt += 1;
+        match &error {
+            AttemptError::Http(e) => warn!("Download failed ({}); retrying in {}s (attempt {}/{})", e, delay.as_secs(), self.attempt, self.retries),
+            AttemptError::BadZip(e) | AttemptError::Write(e) => warn!("{}; retrying in {}s (attempt {}/{})", e, delay.as_secs(), self.attempt, self.retries),
+        }
+        Ok(delay)
+    }
+}
+
+/// Returns the outcome along with how many times the download was retried.
+pub(crate) fn download_zip(client: &Client, url: &str, output_path: &Path, retries: u32, validators: &CacheValidators) -> io::Result<(DownloadStatus, u32)> {
+    info!("Downloading from {}...", url);
+    let mut backoff = Backoff::new(retries);
+    loop {
+        let mut request = client.get(url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let mut file = BufWriter::new(File::create(output_path)?);
+        let result = request
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|mut response| {
+                if response.status() == StatusCode::NOT_MODIFIED {
+                    return Ok(DownloadStatus::NotModified);
+                }
+                let fresh = CacheValidators::from_headers(response.headers());
+                response.copy_to(&mut file)?;
+                Ok(DownloadStatus::Downloaded(fresh))
+            })
+            .map_err(AttemptError::Http)
+            .and_then(|status| {
+                file.flush().map_err(AttemptError::Write)?;
+                if let DownloadStatus::Downloaded(_) = status {
+                    check_zip(output_path).map_err(AttemptError::BadZip)?;
+                }
+                Ok(status)
+            });
+        drop(file);
+
+        match result {
+            Ok(status) => {
+                if let DownloadStatus::Downloaded(_) = status {
+                    info!("Downloaded {} to {}", format_bytes(fs::metadata(output_path)?.len()), output_path.display());
+                }
+                return Ok((status, backoff.retried()));
+            }
+            Err(e) => thread::sleep(backoff.retry(e)?),
+        }
+    }
+}
+
+/// Catches empty and truncated downloads, which a dropped connection can
+/// leave behind without any HTTP error.
+pub(crate) fn check_zip(path: &Path) -> io::Result<()> {
+    if fs::metadata(path)?.len() == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("downloaded {} is empty", path.display())));
+    }
+    open_archive(path).map(drop)
+}
+
+fn open_archive(path: &Path) -> io::Result<ZipArchive<File>> {
+    ZipArchive::new(File::open(path)?).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a valid ZIP archive: {}", path.display(), e))
+    })
+}
+
+/// Renders a byte count for the logs, e.g. `23.4 MB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", value, UNITS[unit]) }
+}
+
+/// Server errors, timeouts and dropped connections are worth retrying;
+/// anything else (404, bad URL, ...) will fail the same way again.
+fn is_retriable(err: &reqwest::Error) -> bool {
+    match err.status() {
+        Some(status) => status.is_server_error(),
+        None => err.is_timeout() || err.is_connect() || err.is_request() || err.is_body() || err.is_decode(),
+    }
+}
+
+/// First two bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Extracts `lua_filename` to `output_path`, refusing entries larger than
+/// `max_size` bytes so a corrupt or hostile archive cannot fill the disk.
+pub(crate) fn extract_lua_from_zip(zip_path: &Path, lua_filename: &str, output_path: &Path, max_size: u64) -> io::Result<()> {
+    let mut archive = open_archive(zip_path)?;
+
+    let Some(index) = find_entry(&archive, lua_filename) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found in ZIP archive", lua_filename),
+        ));
+    };
+    let file_in_zip = archive.by_index(index).map_err(io::Error::other)?;
+    let name = file_in_zip.name().to_string();
+    if file_in_zip.size() > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} claims {} bytes uncompressed, over the {} byte limit", name, file_in_zip.size(), max_size),
+        ));
+    }
+    let compressed = file_in_zip.compressed_size();
+    // The limit applies to what is written, so it also caps a gzipped payload.
+    let mut reader = BufReader::new(file_in_zip);
+    let copied = if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        info!("{} is gzip-compressed inside the ZIP; decompressing it", name);
+        copy_limited(MultiGzDecoder::new(reader), &name, output_path, max_size)?
+    } else {
+        copy_limited(reader, &name, output_path, max_size)?
+    };
+    info!(
+        "Extracted {} ({} from {} compressed, {:.1}x) to {}",
+        name,
+        format_bytes(copied),
+        format_bytes(compressed),
+        copied as f64 / compressed.max(1) as f64,
+        output_path.display()
+    );
+    Ok(())
+}
+
+/// Extracts every file in the archive under `dir`, keeping its directories,
+/// with the same `max_size` limit per file as [`extract_lua_from_zip`].
+/// Entries whose path would leave `dir` are skipped. Returns the files
+/// written.
+pub(crate) fn extract_all_from_zip(zip_path: &Path, dir: &Path, max_size: u64) -> io::Result<Vec<PathBuf>> {
+    let mut archive = open_archive(zip_path)?;
+    let mut written = Vec::new();
+    for index in 0..archive.len() {
+        let file_in_zip = archive.by_index(index).map_err(io::Error::other)?;
+        if file_in_zip.is_dir() {
+            continue;
+        }
+        let name = file_in_zip.name().to_string();
+        let Some(relative) = file_in_zip.enclosed_name() else {
+            warn!("Skipping ZIP entry {} with an unsafe path", name);
+            continue;
+        };
+        if file_in_zip.size() > max_size {
+            warn!("Skipping {}: {} bytes uncompressed is over the {} byte limit", name, file_in_zip.size(), max_size);
+            continue;
+        }
+        let output_path = dir.join(relative);
+        if let Some(parent) = output_path.parent() { fs::create_dir_all(parent)?; }
+        let copied = copy_limited(file_in_zip, &name, &output_path, max_size)?;
+        info!("Extracted {} ({})", output_path.display(), format_bytes(copied));
+        written.push(output_path);
+    }
+    Ok(written)
+}
+
+/// Copies an archive entry to `output_path`. The declared size comes from
+/// the archive itself, so this also stops once `max_size` is passed in case
+/// it lies, removing the partial file.
+fn copy_limited(entry: impl Read, name: &str, output_path: &Path, max_size: u64) -> io::Result<u64> {
+    let mut out_file = File::create(output_path)?;
+    let copied = io::copy(&mut entry.take(max_size.saturating_add(1)), &mut out_file)?;
+    if copied > max_size {
+        drop(out_file);
+        let _ = fs::remove_file(output_path);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is larger than the {} byte limit", name, max_size),
+        ));
+    }
+    Ok(copied)
+}
+
+/// CRC-32 of the entry [`extract_lua_from_zip`] would pick for `lua_filename`,
+/// or `None` when the archive has no such entry.
+pub(crate) fn zip_entry_crc(zip_path: &Path, lua_filename: &str) -> io::Result<Option<u32>> {
+    let mut archive = open_archive(zip_path)?;
+    let Some(index) = find_entry(&archive, lua_filename) else {
+        return Ok(None);
+    };
+    let entry = archive.by_index_raw(index).map_err(io::Error::other)?;
+    Ok(Some(entry.crc32()))
+}
+
+/// Picks the archive entry whose file name is exactly `lua_filename`, in any
+/// directory. A plain suffix match (e.g. `OldPriceTableNA.lua`) is only used
+/// when no entry matches exactly, and `PriceTableNA.lua.gz` only when there
+/// is no `.lua` at all. Names are compared ignoring ASCII case, as archives
+/// built on Windows do not always keep it.
+fn find_entry<R: Read + io::Seek>(archive: &ZipArchive<R>, lua_filename: &str) -> Option<usize> {
+    let wanted = lua_filename.to_ascii_lowercase();
+    let matching = |suffix: &str| -> Vec<(usize, &str)> {
+        (0..archive.len())
+            .filter_map(|i| archive.name_for_index(i).map(|name| (i, name)))
+            .filter(|(_, name)| name.to_ascii_lowercase().ends_with(suffix))
+            .collect()
+    };
+    let mut candidates = matching(&wanted);
+    if candidates.is_empty() {
+        // A gzipped `PriceTableNA.lua.gz` is unpacked by extract_lua_from_zip.
+        candidates = matching(&format!("{}.gz", wanted));
+    }
+    if candidates.len() > 1 {
+        let names: Vec<&str> = candidates.iter().map(|(_, name)| *name).collect();
+        warn!("Several ZIP entries match {}: {}", lua_filename, names.join(", "));
+    }
+    candidates
+        .iter()
+        .find(|(_, name)| {
+            name.rsplit('/').next().is_some_and(|base| {
+                let base = base.to_ascii_lowercase();
+                base == wanted || base.strip_suffix(".gz") == Some(&wanted)
+            })
+        })
+        .or(candidates.first())
+        .map(|(i, _)| *i)
+}
@@ -0,0 +1,134 @@
+//! `async` feature: fetches the NA and EU archives concurrently on a tokio
+//! runtime, then hands each one to the usual synchronous pipeline.
+
+use crate::download::{check_zip, format_bytes, invalid_proxy, AttemptError, Backoff, CacheValidators};
+use crate::server::{load_validators, process_region, region_files};
+use crate::temp::TempFile;
+use crate::{Options, Region, RegionOutput};
+use log::info;
+use reqwest::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use reqwest::{Client, Proxy, StatusCode};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+/// A downloaded archive and the validators to persist once it is processed.
+struct Fetched {
+    zip_path: TempFile,
+    validators: CacheValidators,
+}
+
+/// What [`download_region`] got, plus how many times it retried. `fetched`
+/// is `None` when the server reported the archive unchanged since the last
+/// processed run.
+struct Download {
+    fetched: Option<Fetched>,
+    retries: u32,
+}
+
+/// Downloads both regions at once and processes them one after the other,
+/// returning the outcome for each region processed. With
+/// [`Options::fail_fast`] a failed NA leaves EU out. [`Options::region_delay`]
+/// is not applied; call [`process_server`](crate::process_server) per region
+/// for staggered runs.
+pub fn process_all_regions(options: &Options) -> Vec<(Region, io::Result<RegionOutput>)> {
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => return Region::ALL.iter().map(|&region| (region, Err(io::Error::other(e.to_string())))).collect(),
+    };
+    let (na, eu) = runtime.block_on(download_both(options));
+
+    let mut results = Vec::new();
+    for (region, download) in [(Region::Na, na), (Region::Eu, eu)] {
+        let result = process_fetched(region, download, options);
+        let failed = result.is_err();
+        results.push((region, result));
+        if failed && options.fail_fast {
+            break;
+        }
+    }
+    results
+}
+
+fn build_client(options: &Options) -> io::Result<Client> {
+    let mut builder = Client::builder()
+        .user_agent(&options.user_agent)
+        .connect_timeout(options.timeout)
+        .read_timeout(options.timeout);
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(Proxy::all(proxy).map_err(invalid_proxy)?);
+    }
+    builder.build().map_err(io::Error::other)
+}
+
+/// Downloads the NA and EU archives concurrently. Staggered runs with
+/// [`Options::region_delay`] take the threaded path instead.
+async fn download_both(options: &Options) -> (io::Result<Download>, io::Result<Download>) {
+    let client = match build_client(options) {
+        Ok(client) => client,
+        Err(e) => return (Err(io::Error::new(e.kind(), e.to_string())), Err(e)),
+    };
+    tokio::join!(
+        download_region(&client, Region::Na, options),
+        download_region(&client, Region::Eu, options),
+    )
+}
+
+async fn download_region(client: &Client, region: Region, options: &Options) -> io::Result<Download> {
+    let (zip_name, _) = region_files(region);
+    let url = options.url(region);
+    let zip_path = TempFile::unique(&options.temp_dir, zip_name);
+    let validators = load_validators(region, options);
+
+    info!("Downloading from {}...", url);
+    let mut backoff = Backoff::new(options.retries);
+    loop {
+        let mut request = client.get(url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let mut file = BufWriter::new(File::create(&zip_path)?);
+        let result = async {
+            let mut response = request.send().await?.error_for_status()?;
+            if response.status() == StatusCode::NOT_MODIFIED {
+                return Ok(None);
+            }
+            let fresh = CacheValidators::from_headers(response.headers());
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).map_err(AttemptError::Write)?;
+            }
+            file.flush().map_err(AttemptError::Write)?;
+            check_zip(&zip_path).map_err(AttemptError::BadZip)?;
+            Ok(Some(fresh))
+        }
+        .await;
+        drop(file);
+
+        match result {
+            Ok(Some(fresh)) => {
+                info!("Downloaded {} to {}", format_bytes(fs::metadata(&zip_path)?.len()), zip_path.display());
+                return Ok(Download { fetched: Some(Fetched { zip_path, validators: fresh }), retries: backoff.retried() });
+            }
+            Ok(None) => {
+                info!("{} price table is unchanged since the last run; keeping existing outputs", region);
+                return Ok(Download { fetched: None, retries: backoff.retried() });
+            }
+            Err(e) => tokio::time::sleep(backoff.retry(e)?).await,
+        }
+    }
+}
+
+/// Runs the synchronous pipeline on an archive fetched by [`download_both`],
+/// which removes it (or keeps it with `keep_lua`) and remembers its
+/// validators like a download of its own.
+fn process_fetched(region: Region, download: io::Result<Download>, options: &Options) -> io::Result<RegionOutput> {
+    let Download { fetched, retries } = download?;
+    let Some(Fetched { zip_path, validators }) = fetched else {
+        return Ok(RegionOutput { retries, ..RegionOutput::unchanged(region) });
+    };
+    let output = process_region(region, options, Some((zip_path, validators)))?;
+    Ok(RegionOutput { retries, ..output })
+}
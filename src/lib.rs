@@ -0,0 +1,32 @@
+//! Download and parse Tamriel Trade Centre price tables.
+
+mod diff;
+mod download;
+#[cfg(feature = "async")]
+mod download_async;
+#[cfg(feature = "full-lua")]
+mod lua_ast;
+mod market;
+mod output;
+#[cfg(feature = "parquet")]
+mod parquet_output;
+mod parse;
+mod progress;
+mod region;
+mod server;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod temp;
+
+pub use diff::{diff_entries, print_price_diff, PriceChange, PriceDiff};
+pub use download::DEFAULT_USER_AGENT;
+#[cfg(feature = "async")]
+pub use download_async::process_all_regions;
+#[allow(deprecated)]
+pub use market::parse_items_from_csv_file;
+pub use market::{load_item_names, print_top_items_by_market_cap, read_entries_from_csv, total_average_market_cap};
+pub use output::{format_price, write_entries_to_combined_csv, write_manifest, write_ttc_lua_to_csv, Columns, NameTemplate, OutputFormat};
+pub use parse::{entries_iter, for_each_ttc_entry, parse_item_lookup, parse_ttc_lua, parse_ttc_lua_with, try_parse_ttc_lua, ItemEntry, ParseError, ParseStats, PriceInfo, PriceTable, ITEM_PATH_DEPTH, PRICE_NODE_KEYS, TRAVERSAL_LOG_TARGET};
+pub use region::Region;
+pub use temp::remove_temp_files;
+pub use server::{extract_archive, lookup_csv_name, part_name, process_lookup, process_server, Options, DEFAULT_EU_URL, DEFAULT_NA_URL, OutputFile, RegionOutput};
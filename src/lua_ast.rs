@@ -0,0 +1,87 @@
+//! `full-lua` backend: builds the parsed table straight from a `full_moon`
+//! AST instead of rewriting the Lua source into JSON text.
+
+use crate::parse::numeric_key;
+use crate::ParseError;
+use full_moon::ast::{Expression, Field, LastStmt, UnOp};
+use full_moon::tokenizer::{Symbol, TokenType};
+use serde_json::{Map, Value};
+
+/// Parses the body of `self.PriceTable={...}` into the same `Value` shape
+/// the regex pipeline produces, so `traverse_value` can walk either one.
+pub(crate) fn table_to_value(table_body: &str) -> Result<Value, ParseError> {
+    let source = format!("return {{{}}}", table_body);
+    let ast = full_moon::parse(&source).map_err(|errors| {
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        ParseError::InvalidLua(messages.join("; "))
+    })?;
+
+    match ast.nodes().last_stmt() {
+        Some(LastStmt::Return(ret)) => match ret.returns().iter().next() {
+            Some(expression) => Ok(expression_to_value(expression)),
+            None => Err(ParseError::InvalidLua("empty price table".to_string())),
+        },
+        _ => Err(ParseError::InvalidLua("price table is not a table constructor".to_string())),
+    }
+}
+
+fn expression_to_value(expression: &Expression) -> Value {
+    match expression {
+        Expression::TableConstructor(table) => {
+            let mut map = Map::new();
+            let mut next_index = 1;
+            for field in table.fields() {
+                let (key, value) = match field {
+                    Field::ExpressionKey { key, value, .. } => (key_to_string(key), value.as_ref()),
+                    Field::NameKey { key, value, .. } => (Some(key.token().to_string()), value.as_ref()),
+                    Field::NoKey(value) => {
+                        let key = next_index.to_string();
+                        next_index += 1;
+                        (Some(key), value)
+                    }
+                    _ => continue,
+                };
+                if let Some(key) = key {
+                    map.insert(key, expression_to_value(value));
+                }
+            }
+            Value::Object(map)
+        }
+        Expression::Number(token) => number_value(&token.token().to_string()),
+        Expression::UnaryOperator { unop: UnOp::Minus(_), expression } => match expression.as_ref() {
+            Expression::Number(token) => number_value(&format!("-{}", token.token())),
+            _ => Value::Null,
+        },
+        Expression::String(token) => match token.token_type() {
+            TokenType::StringLiteral { literal, .. } => Value::String(literal.to_string()),
+            _ => Value::Null,
+        },
+        Expression::Symbol(token) => match token.token_type() {
+            TokenType::Symbol { symbol: Symbol::True } => Value::Bool(true),
+            TokenType::Symbol { symbol: Symbol::False } => Value::Bool(false),
+            _ => Value::Null,
+        },
+        _ => Value::Null,
+    }
+}
+
+/// Renders a `[key]` expression the way the regex pipeline would: strings
+/// by their contents and numbers through [`numeric_key`].
+fn key_to_string(key: &Expression) -> Option<String> {
+    match key {
+        Expression::String(token) => match token.token_type() {
+            TokenType::StringLiteral { literal, .. } => Some(literal.to_string()),
+            _ => None,
+        },
+        Expression::Number(token) => Some(numeric_key(&token.token().to_string())),
+        Expression::UnaryOperator { unop: UnOp::Minus(_), expression } => match expression.as_ref() {
+            Expression::Number(token) => Some(numeric_key(&format!("-{}", token.token()))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn number_value(text: &str) -> Value {
+    serde_json::from_str(text).unwrap_or(Value::Null)
+}
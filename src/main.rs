@@ -1,565 +1,616 @@
-use chrono::{DateTime, Datelike, Utc};
-use csv::ReaderBuilder;
+use clap::{Parser, Subcommand};
+use log::{error, info, warn, LevelFilter};
 use num_format::{Locale, ToFormattedString};
-use regex::Regex;
-use reqwest::blocking::get;
-use serde::Deserialize;
-use zip::ZipArchive;
-use std::collections::{BTreeMap, HashMap};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::env;
 use std::error::Error;
-use std::fs::{self, File};
-use std::io::{self, BufWriter, Write};
-use std::path::Path;
-
-#[derive(Debug, Deserialize)]
-pub struct PriceInfo {
-    #[serde(rename = "A")]
-    pub avg: f64,
-    #[serde(rename = "X")]
-    pub max: f64,
-    #[serde(rename = "N")]
-    pub min: f64,
-    #[serde(rename = "EC")]
-    pub entry_count: u32,
-    #[serde(rename = "AC")]
-    pub amount_count: u32,
-    #[serde(rename = "S")]
-    pub suggested_price: Option<f64>,
-    #[serde(rename = "SA")]
-    pub sale_avg: Option<f64>,
-    #[serde(rename = "SE")]
-    pub sale_entry_count: Option<u32>,
-    #[serde(rename = "SAC")]
-    pub sale_amount_count: Option<u32>,
+use std::fs::{self, File, TryLockError};
+use std::io;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use ttcdata::{diff_entries, extract_archive, load_item_names, lookup_csv_name, part_name, print_price_diff, print_top_items_by_market_cap, process_lookup, process_server, read_entries_from_csv, remove_temp_files, total_average_market_cap, write_entries_to_combined_csv, write_manifest, Columns, NameTemplate, Options, OutputFormat, Region, RegionOutput, DEFAULT_EU_URL, DEFAULT_NA_URL, DEFAULT_USER_AGENT, TRAVERSAL_LOG_TARGET};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Download and archive Tamriel Trade Centre price tables",
+    after_help = "Exit codes: 0 all regions processed, 2 some regions or a later step failed, 3 nothing processed, 4 bad arguments."
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Region to process (NA or EU). Both regions are processed when omitted.
+    #[arg(long)]
+    region: Option<Region>,
+
+    /// Number of times to retry a download after a transient failure.
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// Connect and read timeout for downloads, in seconds.
+    #[arg(long, default_value_t = 30)]
+    timeout_secs: u64,
+
+    /// User-Agent header to send with downloads.
+    #[arg(long, default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+
+    /// Output format for price entries: csv, json, both, ndjson, tsv or
+    /// (with the `parquet` feature) parquet.
+    #[arg(long, default_value = "csv")]
+    format: OutputFormat,
+
+    /// Comma-separated CSV columns to write, in order, e.g.
+    /// item_id,avg,suggested_price. All columns are written when omitted.
+    #[arg(long, value_name = "LIST")]
+    columns: Option<Columns>,
+
+    /// Language of the item lookup table, e.g. EN, DE or FR.
+    #[arg(long, default_value = "EN")]
+    language: String,
+
+    /// Also write the lookup tables of these languages from the same
+    /// archive, e.g. DE,FR,RU, each to its own lookup_<lang>.csv.
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    languages: Vec<String>,
+
+    /// Directory the dated folders and latest/ are written under.
+    #[arg(long, default_value = ".")]
+    output_dir: PathBuf,
+
+    /// Directory for temporary download files. Defaults to the system temp directory.
+    #[arg(long)]
+    temp_dir: Option<PathBuf>,
+
+    /// Download and parse the price tables without writing any output.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Keep the downloaded ZIP and extracted Lua in the output directory.
+    #[arg(long)]
+    keep_lua: bool,
+
+    /// Also write every processed region to this single CSV, with a leading region column.
+    #[arg(long)]
+    combined: Option<PathBuf>,
+
+    /// Only write entries of this quality, as the number used in the price
+    /// table: 1 normal, 2 fine, 3 superior, 4 epic, 5 legendary. Repeatable.
+    #[arg(long = "quality", value_name = "QUALITY")]
+    qualities: Vec<String>,
+
+    /// Drop entries seen in fewer than N listings; 0 keeps everything.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_entry_count: u64,
+
+    /// Drop entries without recorded sale data (no SA sale average).
+    #[arg(long)]
+    require_sale: bool,
+
+    /// Multiply the prices by N to write gold per stack of N instead of per
+    /// unit, e.g. 200. Counts are unchanged.
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    per_stack: u32,
+
+    /// Fail a region whose price table parses into fewer than N entries,
+    /// which usually means the TTC format changed; 0 disables the check.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_expected_entries: usize,
+
+    /// Split each CSV into files of at most N rows, na_part0.csv,
+    /// na_part1.csv and so on, each with the header.
+    #[arg(long, value_name = "N")]
+    split: Option<NonZeroUsize>,
+
+    /// Gzip the CSV and JSON outputs, writing e.g. latest/na.csv.gz.
+    #[arg(long)]
+    gzip: bool,
+
+    /// Write only the first N entries per region. Meant for development: the
+    /// full table is still downloaded and parsed, the cut happens afterwards.
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Refuse to extract a file from the downloaded ZIP larger than this many megabytes.
+    #[arg(long, default_value_t = 500)]
+    max_extract_mb: u64,
+
+    /// Process this local PriceTable .zip or .lua file instead of downloading.
+    /// Without --region, the region is taken from the file name
+    /// (PriceTableEU.lua).
+    #[arg(long)]
+    input: Option<PathBuf>,
+
+    /// Extract every file in the region's archive into DIR and exit without
+    /// parsing, to see what TTC ships. Needs --region unless --input names
+    /// the region.
+    #[arg(long, value_name = "DIR")]
+    extract_all: Option<PathBuf>,
+
+    /// Skip writing a region whose price table is older than this Unix timestamp.
+    #[arg(long, value_name = "UNIX")]
+    since: Option<i64>,
+
+    /// IANA timezone, e.g. Europe/Berlin, whose date names the dated output folder.
+    #[arg(long, default_value = "UTC")]
+    timezone: chrono_tz::Tz,
+
+    /// URL to download the NA price table ZIP from.
+    #[arg(long, env = "TTC_NA_URL", default_value = DEFAULT_NA_URL)]
+    na_url: String,
+
+    /// URL to download the EU price table ZIP from.
+    #[arg(long, env = "TTC_EU_URL", default_value = DEFAULT_EU_URL)]
+    eu_url: String,
+
+    /// Send downloads through this proxy, e.g. http://proxy:3128. Without it
+    /// the HTTP_PROXY, HTTPS_PROXY and NO_PROXY variables are honoured.
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// Only log warnings and errors, and hide the progress spinner.
+    #[arg(long, short)]
+    quiet: bool,
+
+    /// Log every leaf of the price table as it is walked: its path and
+    /// whether it was read as a price node. Very noisy, whatever RUST_LOG says.
+    #[arg(long, short)]
+    verbose: bool,
+
+    /// Only write the dated snapshots, never the latest/ copies.
+    #[arg(long)]
+    no_latest: bool,
+
+    /// Add the table's Unix timestamp to the file names in the dated folder
+    /// (na_1700001234.csv.zst) so several tables a day are all kept.
+    #[arg(long)]
+    timestamp_names: bool,
+
+    /// Where each region's dated files go under the output directory, before
+    /// the extension. Placeholders: {region}, {date}, {year}, {month}, {day}
+    /// and {timestamp}.
+    #[arg(long, value_name = "TEMPLATE", default_value = "{year}/{month}/{day}/{region}")]
+    name_template: NameTemplate,
+
+    /// Write the JSON the price table was converted into, before entries are
+    /// read from it, to this path (the region is added to the file name).
+    #[arg(long, value_name = "PATH")]
+    dump_json: Option<PathBuf>,
+
+    /// Drop entries whose prices are inconsistent (min > avg, avg > max, or
+    /// negative) instead of only warning about them.
+    #[arg(long)]
+    strict: bool,
+
+    /// Start every CSV and TSV row with a region column (na or eu), so files
+    /// from both servers can be concatenated.
+    #[arg(long)]
+    region_column: bool,
+
+    /// Run everything a failed region leaves possible: the remaining regions
+    /// of a --region-delay-secs run, the market report and --combined. The
+    /// exit code is still non-zero.
+    #[arg(long, overrides_with = "fail_fast")]
+    keep_going: bool,
+
+    /// Stop at the first failed region (the default). Regions already
+    /// running alongside it still finish.
+    #[arg(long, overrides_with = "keep_going")]
+    fail_fast: bool,
+
+    /// Process the regions one after another, waiting this many seconds
+    /// between them, instead of downloading both at once.
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    region_delay_secs: u64,
+
+    /// Also write na_quality_stats.csv (and eu_...) with the entry count,
+    /// mean average price and total quantity per quality tier.
+    #[arg(long)]
+    quality_stats: bool,
+
+    /// Also write na.schema.json (and eu...) next to the CSV, giving the
+    /// type of each column for DataFrame loaders.
+    #[arg(long)]
+    schema: bool,
+
+    /// Also upsert the price entries into this SQLite database.
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    sqlite: Option<PathBuf>,
 }
 
-#[derive(Debug)]
-pub struct ItemEntry {
-    pub item_id: String,
-    pub quality: String,
-    pub level: String,
-    pub trait_id: String,
-    pub variant: String,
-    pub price: PriceInfo,
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Compare two price CSVs and report price moves, new and removed entries.
+    Diff {
+        /// The older snapshot (.csv, .csv.gz or .csv.zst).
+        old: PathBuf,
+        /// The newer snapshot.
+        new: PathBuf,
+        /// Only report entries whose average price moved by more than this percentage.
+        #[arg(long, default_value_t = 10.0)]
+        threshold: f64,
+        /// Print the differences as JSON instead of a table.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Download the archive and write only the item lookup CSV to latest/,
+    /// skipping the price table. Takes the usual options before `lookup`.
+    Lookup {
+        /// Region whose archive to read the names from.
+        #[arg(long, default_value = "NA")]
+        region: Region,
+    },
 }
 
-fn download_zip(url: &str, output_path: &str) -> io::Result<()> {
-    println!("Downloading from {}...", url);
-    let response = get(url).expect("Failed to download file");
-    let bytes = response.bytes().expect("Failed to read bytes");
-
-    let mut file = File::create(output_path)?;
-    file.write_all(&bytes)?;
-    println!("Downloaded ZIP to {}", output_path);
-    Ok(())
+/// Exit codes, kept stable for scripts. 130 after Ctrl-C comes from the
+/// interrupt handler instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    /// Every region was processed, or skipped as unchanged.
+    Ok = 0,
+    /// Some regions failed, or they all succeeded and a later step such as
+    /// the manifest, the report or `--combined` did not.
+    Partial = 2,
+    /// No region was processed, or the run failed before reaching them.
+    Failed = 3,
+    /// The command line could not be used.
+    Usage = 4,
 }
 
-fn extract_lua_from_zip(zip_path: &str, lua_filename: &str, output_path: &str) -> io::Result<()> {
-    let file = File::open(zip_path)?;
-    let mut archive = ZipArchive::new(file).expect("Failed to read ZIP archive");
-
-    for i in 0..archive.len() {
-        let mut file_in_zip = archive.by_index(i).unwrap();
-        if file_in_zip.name().ends_with(lua_filename) {
-            let mut out_file = File::create(output_path)?;
-            io::copy(&mut file_in_zip, &mut out_file)?;
-            println!("Extracted {} to {}", lua_filename, output_path);
-            return Ok(());
+fn main() {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(e) => {
+            // --help and --version also come through here, on stdout.
+            let exit = if e.use_stderr() { Exit::Usage } else { Exit::Ok };
+            let _ = e.print();
+            process::exit(exit as i32);
         }
+    };
+    let default_level = if args.quiet { "warn" } else { "info" };
+    let mut logger = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level));
+    if args.verbose {
+        logger.filter_module(TRAVERSAL_LOG_TARGET, LevelFilter::Trace);
     }
-
-    Err(io::Error::new(
-        io::ErrorKind::NotFound,
-        format!("{} not found in ZIP archive", lua_filename),
-    ))
+    logger.init();
+    // Downloads and extractions in flight would otherwise be left in the
+    // temp dir; 130 is the usual exit code after SIGINT.
+    if let Err(e) = ctrlc::set_handler(|| {
+        remove_temp_files();
+        process::exit(130);
+    }) {
+        warn!("Could not install the Ctrl-C handler: {}", e);
+    }
+    // The panic hook has already printed the message.
+    let exit = match panic::catch_unwind(AssertUnwindSafe(|| run(args))) {
+        Ok(Ok(exit)) => exit,
+        Ok(Err(e)) => {
+            error!("{}", e);
+            Exit::Failed
+        }
+        Err(_) => Exit::Failed,
+    };
+    process::exit(exit as i32);
 }
 
-fn extract_price_table(text: &str) -> String {
-    let no_comments = Regex::new(r"--.*").unwrap().replace_all(text, "").to_string();
-    if let Some(pos) = no_comments.find("self.PriceTable") {
-        if let Some(open_brace_pos) = no_comments[pos..].find('{') {
-            let mut idx = pos + open_brace_pos;
-            let mut depth: i32 = 0;
-            let chars: Vec<char> = no_comments.chars().collect();
-            let mut in_string: Option<char> = None;
-            let mut escaped = false;
-            let mut start = None;
-            let mut end = None;
-            while idx < chars.len() {
-                let c = chars[idx];
-                if in_string.is_some() {
-                    if escaped {
-                        escaped = false;
-                    } else if c == '\\' {
-                        escaped = true;
-                    } else if Some(c) == in_string {
-                        in_string = None;
-                    }
-                } else {
-                    if c == '\'' || c == '"' {
-                        in_string = Some(c);
-                    } else if c == '{' {
-                        if depth == 0 {
-                            start = Some(idx + 1);
-                        }
-                        depth += 1;
-                        } else if c == '}' {
-                            depth -= 1;
-                        if depth == 0 {
-                            end = Some(idx);
-                        break;
-                        }
-                    }
-                }
-                idx += 1;
-            }
-
-            if let (Some(s), Some(e)) = (start, end) {
-                return chars[s..e].iter().collect::<String>().trim().to_string();
-            }
+/// Everything after logging is set up. An error means nothing was processed;
+/// failures after that are reported through the returned [`Exit`].
+fn run(args: Args) -> Result<Exit, Box<dyn Error>> {
+    if let Some(Command::Diff { old, new, threshold, json }) = &args.command {
+        let read = |path: &PathBuf| read_entries_from_csv(path).map_err(|e| format!("{}: {}", path.display(), e));
+        let diff = diff_entries(&read(old)?, &read(new)?, *threshold);
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+        } else {
+            print_price_diff(&diff);
         }
+        return Ok(Exit::Ok);
     }
+    // `lookup` has its own --region.
+    let region = match (args.region, &args.input) {
+        (None, Some(input)) if !matches!(args.command, Some(Command::Lookup { .. })) => {
+            let Some(region) = Region::from_file_name(input) else {
+                error!("Cannot tell the region from {}; pass --region NA or --region EU", input.display());
+                return Ok(Exit::Usage);
+            };
+            info!("Processing {} as {}", input.display(), region);
+            Some(region)
+        }
+        (region, _) => region,
+    };
+    let regions = match region {
+        Some(region) => vec![region],
+        None => Region::ALL.to_vec(),
+    };
+    let options = Options {
+        retries: args.retries,
+        timeout: Duration::from_secs(args.timeout_secs),
+        region_delay: Duration::from_secs(args.region_delay_secs),
+        fail_fast: !args.keep_going,
+        user_agent: args.user_agent,
+        format: args.format,
+        columns: args.columns,
+        language: args.language,
+        extra_languages: args.languages,
+        output_dir: args.output_dir,
+        temp_dir: args.temp_dir.unwrap_or_else(env::temp_dir),
+        dry_run: args.dry_run,
+        keep_lua: args.keep_lua,
+        qualities: args.qualities,
+        min_entry_count: args.min_entry_count,
+        require_sale: args.require_sale,
+        min_expected_entries: args.min_expected_entries,
+        per_stack: args.per_stack,
+        split: args.split,
+        gzip: args.gzip,
+        limit: args.limit,
+        max_extract_size: args.max_extract_mb * 1024 * 1024,
+        input: args.input,
+        since: args.since,
+        timezone: args.timezone,
+        na_url: args.na_url,
+        eu_url: args.eu_url,
+        proxy: args.proxy,
+        quiet: args.quiet,
+        no_latest: args.no_latest,
+        name_template: args.name_template,
+        timestamp_names: args.timestamp_names,
+        dump_json: args.dump_json,
+        strict: args.strict,
+        quality_stats: args.quality_stats,
+        schema: args.schema,
+        region_column: args.region_column,
+        #[cfg(feature = "sqlite")]
+        sqlite: args.sqlite,
+    };
 
-    panic!("Could not locate self.PriceTable={{...}} block");
-}
-
-fn extract_item_lookup_table(text: &str) -> Option<String> {
-    let re = Regex::new(r"self\.ItemLookUpTable\s*=\s*\{(?s)(?P<body>.*?)\}\s*end").unwrap();
-    if let Some(caps) = re.captures(text) {
-        return Some(caps["body"].to_string());
+    // Held until main returns. Dry runs and --extract-all take it too, as
+    // they still download and can write --dump-json or the extracted files.
+    let _lock = lock_output_dir(&options.output_dir)?;
+
+    if let Some(dir) = &args.extract_all {
+        let Some(region) = region else {
+            error!("--extract-all needs --region NA or --region EU");
+            return Ok(Exit::Usage);
+        };
+        let files = extract_archive(region, &options, dir)?;
+        println!("Extracted {} files to {}", files.len(), dir.display());
+        return Ok(Exit::Ok);
     }
-    None
-}
 
-fn extract_timestamp_from_block(block: &str) -> Option<i64> {
-    let re = Regex::new(r#"\[?\s*['\"]?TimeStamp['\"]?\s*\]?\s*=\s*(\d+)"#).unwrap();
-        if let Some(caps) = re.captures(block) {
-            if let Some(m) = caps.get(1) {
-                if let Ok(ts) = m.as_str().parse::<i64>() {
-                    return Some(ts);
+    if let Some(Command::Lookup { region }) = args.command {
+        let paths = process_lookup(region, &options)?;
+        if !options.dry_run {
+            for path in paths {
+                println!("{}", path.display());
             }
         }
+        return Ok(Exit::Ok);
     }
-    None
-}
-
-fn lua_to_json(lua: &str) -> String {
-    let mut s = lua.to_string();
-    s = s.replace("\r\n", "\n");
-    s = Regex::new(r"'([^'\\]*(?:\\.[^'\\]*)*)'")
-        .unwrap()
-        .replace_all(&s, r#""$1""#)
-        .to_string();
-    s = Regex::new(r#"\[\s*"([^"]*)"\s*\]\s*="#)
-        .unwrap()
-        .replace_all(&s, r#""$1":"#)
-        .to_string();
-    s = Regex::new(r#"\[\s*'([^']*)'\s*\]\s*="#)
-        .unwrap()
-        .replace_all(&s, r#""$1":"#)
-        .to_string();
-    s = Regex::new(r#"\[\s*(-?\d+(?:\.\d+)?)\s*\]\s*="#)
-        .unwrap()
-        .replace_all(&s, r#""$1":"#)
-        .to_string();
-    s = Regex::new(r#"(?P<prefix>(?:\{|,|\[|\s))(?P<key>[A-Za-z_]\w*)\s*="#)
-        .unwrap()
-        .replace_all(&s, "${prefix}\"${key}\":")
-        .to_string();
-    s = Regex::new(r"\bnil\b").unwrap().replace_all(&s, "null").to_string();
-    s = Regex::new(r"\btrue\b").unwrap().replace_all(&s, "true").to_string();
-    s = Regex::new(r"\bfalse\b").unwrap().replace_all(&s, "false").to_string();
-
-    s
-}
 
-fn remove_trailing_commas_recursive(mut text: String) -> String {
-    let re = Regex::new(r",\s*(?P<close>[\}\]])").unwrap();
-    loop {
-        let new_text = re.replace_all(&text, "$close").to_string();
-        if new_text == text {
-            break;
+    // With the async feature a full run downloads both regions on one
+    // runtime; single regions, local files and staggered runs keep the
+    // threaded path.
+    #[cfg(feature = "async")]
+    let results: Vec<_> = if region.is_none() && options.input.is_none() && options.region_delay.is_zero() {
+        // Both regions share the runtime, so a panic takes down the pair.
+        match panic::catch_unwind(AssertUnwindSafe(|| ttcdata::process_all_regions(&options))) {
+            Ok(results) => results.into_iter().map(|(region, result)| (region, Ok(result))).collect(),
+            Err(payload) => {
+                let reason = panic_message(&*payload).to_string();
+                regions.iter().map(|&region| (region, Err(Box::new(reason.clone()) as Box<dyn Any + Send>))).collect()
+            }
         }
-        text = new_text;
-    }
-
-    text = Regex::new(r",\s*$").unwrap().replace_all(&text, "").to_string();
-
-    text
-}
-
-fn traverse_value(value: &serde_json::Value, path: &mut Vec<String>, results: &mut Vec<ItemEntry>) {
-    if let serde_json::Value::Object(map) = value {
-        for (k, v) in map {
-            path.push(k.clone());
-
-            if let serde_json::Value::Object(inner) = v {
-                if inner.contains_key("A") && inner.contains_key("X") {
-                    let price: PriceInfo =
-                        serde_json::from_value(serde_json::Value::Object(inner.clone()))
-                            .expect("Failed to parse PriceInfo");
-                    
-                    let mut p = path.clone();
-                    while p.len() < 5 { p.push("".to_string()); }
-                    results.push(ItemEntry {
-                        item_id: p[0].clone(),
-                        quality: p[1].clone(),
-                        level: p[2].clone(),
-                        trait_id: p[3].clone(),
-                        variant: p[4].clone(),
-                        price,
-                    });
-                } else {
-                    traverse_value(v, path, results);
-                }
+    } else {
+        process_on_threads(&regions, &options)
+    };
+    #[cfg(not(feature = "async"))]
+    let results = process_on_threads(&regions, &options);
+
+    let mut failures: Vec<(Region, String)> = Vec::new();
+    let mut outputs = Vec::new();
+    for (region, result) in results {
+        match result {
+            Ok(Ok(output)) => outputs.push(output),
+            Ok(Err(e)) => {
+                error!("Failed to process {}: {}", region, e);
+                failures.push((region, e.to_string()));
+            }
+            Err(payload) => {
+                let reason = format!("panicked: {}", panic_message(&*payload));
+                error!("Failed to process {}: {}", region, reason);
+                failures.push((region, reason));
             }
-            path.pop();
         }
     }
-}
-
-
-
-fn parse_ttc_lua(lua_text: &str) -> (Vec<ItemEntry>, Option<i64>) {
-    let extracted = extract_price_table(&lua_text);
-    let timestamp = extract_timestamp_from_block(&extracted);
-
-    let jsonish = lua_to_json(&extracted);
-    let cleaned = remove_trailing_commas_recursive(jsonish);
-    let wrapped = format!("{{{}}}", cleaned);
-    let parsed: serde_json::Value =
-    serde_json::from_str(&wrapped).expect("Failed to parse JSON from TTC Lua file");
-    let mut results = Vec::new();
-    let mut path = Vec::new();
-    traverse_value(&parsed["Data"], &mut path, &mut results);
-
-    (results, timestamp)
-}
-
-fn parse_item_lookup(lua_text: &str) -> BTreeMap<String, String> {
-    let mut map = BTreeMap::new();
-    if let Some(body) = extract_item_lookup_table(lua_text) {
-        let re = Regex::new(r#"\[\s*\"([^\"]+)\"\s*\]\s*=\s*\{\s*\[\s*\d+\s*\]\s*=\s*(\d+)\s*,?\s*\}"#).unwrap();
-        for cap in re.captures_iter(&body) {
-            let name = cap[1].to_string();
-            let id = cap[2].to_string();
-            map.insert(id, name);
-        }
+    // With nothing processed there is no lookup or CSV to report on, and
+    // failing to load them would hide the real errors.
+    let reported = if options.dry_run || outputs.is_empty() {
+        Ok(())
+    } else {
+        write_reports(&regions, &options, &outputs, &failures, args.combined.as_deref())
+    };
+    print_summary(&regions, &outputs, &failures, options.dry_run);
+    if let Err(e) = &reported {
+        error!("{}", e);
     }
-    map
-}
 
-fn write_entries_to_csv_manual(entries: &[ItemEntry], path: &str) -> std::io::Result<()> {
-    if let Some(parent) = Path::new(path).parent() { fs::create_dir_all(parent)?; }
-    let mut file = File::create(path)?;
-    let header = ["item_id", "quality", "level", "trait", "variant", "avg", "max", "min", "entry_count", "amount_count", "suggested_price", "sale_avg", "sale_entry_count", "sale_amount_count"].join(",");
-    writeln!(file, "{}", header)?;
-    for e in entries {
-        let parts = vec![ e.item_id.clone(), e.quality.clone(), e.level.clone(), e.trait_id.clone(), e.variant.clone(), e.price.avg.to_string(), e.price.max.to_string(), e.price.min.to_string(), e.price.entry_count.to_string(), e.price.amount_count.to_string(), e.price.suggested_price.map_or("".to_string(), |v| v.to_string()), e.price.sale_avg.map_or("".to_string(), |v| v.to_string()), e.price.sale_entry_count.map_or("".to_string(), |v| v.to_string()), e.price.sale_amount_count.map_or("".to_string(), |v| v.to_string()), ];
-        let row = parts.iter().map(|s| s.as_str()).collect::<Vec<&str>>().join(",");
-        writeln!(file, "{}", row)?;
+    if !failures.is_empty() {
+        let names: Vec<String> = failures.iter().map(|(region, _)| region.to_string()).collect();
+        error!("Failed to process region(s): {}", names.join(", "));
     }
-    Ok(())
+    Ok(if outputs.is_empty() && !failures.is_empty() {
+        Exit::Failed
+    } else if !failures.is_empty() || reported.is_err() {
+        Exit::Partial
+    } else {
+        Exit::Ok
+    })
 }
 
-fn write_entries_to_csv_zst(entries: &[ItemEntry], path: &str) -> io::Result<()> {
-    if let Some(parent) = Path::new(path).parent() {
-        fs::create_dir_all(parent)?;
-    }
-    let file = File::create(path)?;
-    let mut encoder = zstd::Encoder::new(BufWriter::new(file), 3)?;
-
-    let header = [
-        "item_id", "quality", "level", "trait", "variant",
-        "avg", "max", "min", "entry_count", "amount_count",
-        "suggested_price", "sale_avg", "sale_entry_count", "sale_amount_count",
-    ]
-    .join(",");
-    writeln!(encoder, "{}", header)?;
-
-    for e in entries {
-        let parts = vec![
-            e.item_id.clone(),
-            e.quality.clone(),
-            e.level.clone(),
-            e.trait_id.clone(),
-            e.variant.clone(),
-            e.price.avg.to_string(),
-            e.price.max.to_string(),
-            e.price.min.to_string(),
-            e.price.entry_count.to_string(),
-            e.price.amount_count.to_string(),
-            e.price.suggested_price.map_or("".to_string(), |v| v.to_string()),
-            e.price.sale_avg.map_or("".to_string(), |v| v.to_string()),
-            e.price.sale_entry_count.map_or("".to_string(), |v| v.to_string()),
-            e.price.sale_amount_count.map_or("".to_string(), |v| v.to_string()),
-        ];
-        writeln!(encoder, "{}", parts.join(","))?;
+/// Writes the manifest, prints the market report and builds `--combined`
+/// once the regions are done.
+fn write_reports(
+    regions: &[Region],
+    options: &Options,
+    outputs: &[RegionOutput],
+    failures: &[(Region, String)],
+    combined: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    write_manifest(outputs, &options.output_dir.join("manifest.json"))?;
+    if !failures.is_empty() && options.fail_fast {
+        warn!("Skipping the market report and --combined after a failed region; --keep-going runs them anyway");
+        return Ok(());
     }
 
-    encoder.finish()?;
-    Ok(())
-}
-
-fn write_lookup_table(lookup_map: &BTreeMap<String, String>, path: &str) -> std::io::Result<()> {
-    if let Some(parent) = Path::new(path).parent() { fs::create_dir_all(parent)?; }
-    let mut file = File::create(path)?;
-    let header = ["item_id", "item_name"].join(",");
-    writeln!(file, "{}", header)?;
-    for (id, name) in lookup_map.iter() {
-        let quoted_name = format!("\"{}\"", name);
-        let parts = vec![ id, &quoted_name];
-        let row = parts.iter().map(|s| s.as_str()).collect::<Vec<&str>>().join(",");
-        writeln!(file, "{}", row)?;
+    // The report and --combined read latest/, which --no-latest leaves alone.
+    if options.no_latest {
+        if combined.is_some() {
+            warn!("--combined is built from the latest/ CSVs, which --no-latest skips; skipping it");
+        }
+        return Ok(());
     }
-    Ok(())
-}
-
-fn write_lookup_table_zst(lookup_map: &BTreeMap<String, String>, path: &str) -> io::Result<()> {
-    if let Some(parent) = Path::new(path).parent() {
-        fs::create_dir_all(parent)?;
+    if options.columns.is_some() {
+        warn!("--columns leaves out fields the market report and --combined read; skipping them");
+        return Ok(());
     }
-    let file = File::create(path)?;
-    let mut encoder = zstd::Encoder::new(BufWriter::new(file), 3)?;
 
-    writeln!(encoder, "item_id,item_name")?;
-    for (id, name) in lookup_map.iter() {
-        writeln!(encoder, "{},\"{}\"", id, name)?;
+    let latest_dir = options.latest_dir();
+    let gz = options.gz_suffix();
+    let item_lookup = load_item_names(latest_dir.join(format!("{}{}", lookup_csv_name(&options.language), gz)))
+        .or_else(|_| load_item_names(latest_dir.join(format!("lookup.csv{}", gz))))
+        .unwrap_or_else(|e| {
+            warn!("Could not load item names from {}: {}", latest_dir.display(), e);
+            Default::default()
+        });
+    if options.per_stack != 1 {
+        warn!("--per-stack prices would overstate the market cap report; skipping it");
     }
-
-    encoder.finish()?;
-    Ok(())
-}
-
-fn process_server(region: &str, latest_csv: &str) -> io::Result<()> {
-    let (url, zip_path, lua_filename, lookup_filename, csv_prefix) = match region {
-        "NA" => (
-            "https://us.tamrieltradecentre.com/download/PriceTable",
-            "PriceTableNA.zip",
-            "PriceTableNA.lua",
-            "ItemLookUpTable_EN.lua",
-            "na",
-        ),
-        "EU" => (
-            "https://eu.tamrieltradecentre.com/download/PriceTable",
-            "PriceTableEU.zip",
-            "PriceTableEU.lua",
-            "ItemLookUpTable_EN.lua",
-            "eu",
-        ),
-        _ => panic!("Unknown region: {}", region),
-    };
-
-    let lua_output = lua_filename;
-    let lookup_output = lookup_filename;
-    download_zip(url, zip_path)?;
-    extract_lua_from_zip(zip_path, lua_filename, lua_output)?;
-
-    let mut lookup_map: BTreeMap<String, String> = BTreeMap::new();
-
-    if let Ok(()) = extract_lua_from_zip(zip_path, lookup_filename, lookup_output) {
-        let lookup_text = fs::read_to_string(lookup_output).expect("Could not read lookup Lua file");
-        lookup_map = parse_item_lookup(&lookup_text);
-        if Path::new(lookup_output).exists() { 
-            fs::remove_file(lookup_output)?; 
+    let mut region_entries = Vec::new();
+    for &region in regions {
+        if failures.iter().any(|(failed, _)| *failed == region) || !options.format.writes_csv() {
+            continue;
         }
-    } else {
-        println!("Warning: {} not found in ZIP archive; item names will be empty", lookup_filename);
-    }
-    let lua_text = fs::read_to_string(lua_output).expect("Could not read Lua file");
-    let (entries, timestamp_opt) = parse_ttc_lua(&lua_text);
-    println!("Parsed {} price entries for {}.", entries.len(), region);
-
-    let timestamp = timestamp_opt.unwrap_or_else(|| Utc::now().timestamp()); 
-    let ndt = DateTime::from_timestamp(timestamp, 0).unwrap();
-    let folder = format!("{:04}/{:02}/{:02}", ndt.year(), ndt.month(), ndt.day());
-    fs::create_dir_all(&folder)?;
-
-    let csv_path = format!("{}/{}.csv.zst", folder, csv_prefix);
-    write_entries_to_csv_zst(&entries, &csv_path)?;
-    write_entries_to_csv_manual(&entries, latest_csv)?;
-
-    let latest_lookup_path = "latest/lookup.csv";
-    if lookup_has_changed(&lookup_map, latest_lookup_path) {
-        let dated_lookup_path = format!("{}/lookup.csv", folder);
-        write_lookup_table_zst(&lookup_map, &dated_lookup_path)?;
-        write_lookup_table(&lookup_map, latest_lookup_path)?;
+        let csv_path = |part| latest_dir.join(format!("{}.csv{}", part_name(region.csv_prefix(), part, options.split), gz));
+        // A region skipped as unchanged or stale may not have a CSV yet.
+        if !csv_path(0).exists() {
+            warn!("No {} to report on for {}", csv_path(0).display(), region);
+            continue;
+        }
+        let mut entries = Vec::new();
+        for part in 0.. {
+            let path = csv_path(part);
+            if part > 0 && (options.split.is_none() || !path.exists()) {
+                break;
+            }
+            entries.extend(read_entries_from_csv(&path)?);
+        }
+        // The market cap sums avg * amount_count, which per-stack prices
+        // overstate N times.
+        if options.per_stack != 1 {
+            region_entries.push((region, entries));
+            continue;
+        }
+        let market_cap = total_average_market_cap(&entries);
+        println!("\nPC {} Gold Market Cap Estimate: {} (based on available TTC data)", region, market_cap.to_formatted_string(&Locale::en));
+        print_top_items_by_market_cap(&entries, &item_lookup);
+        region_entries.push((region, entries));
     }
 
-    if Path::new(zip_path).exists() { fs::remove_file(zip_path)?; }
-    if Path::new(lua_output).exists() { fs::remove_file(lua_output)?; }
-
-    Ok(())
-}
-
-#[derive(Debug, Deserialize)]
-struct CsvRow {
-    item_id: String,
-    quality: String,
-    level: String,
-    #[serde(rename = "trait")]
-    trait_id: String,
-    variant: String,
-
-    avg: f64,
-    max: f64,
-    min: f64,
-    entry_count: u32,
-    amount_count: u32,
-
-    suggested_price: Option<f64>,
-    sale_avg: Option<f64>,
-    sale_entry_count: Option<u32>,
-    sale_amount_count: Option<u32>,
-}
-
-pub fn parse_items_from_csv_file(path: &str) -> Result<Vec<ItemEntry>, Box<dyn Error>> {
-    let file = File::open(path)?;
-    let mut reader = csv::Reader::from_reader(file);
-
-    let mut entries = Vec::new();
-
-    for result in reader.deserialize::<CsvRow>() {
-        let row = result?;
-
-        entries.push(ItemEntry {
-            item_id: row.item_id,
-            quality: row.quality,
-            level: row.level,
-            trait_id: row.trait_id,
-            variant: row.variant,
-            price: PriceInfo {
-                avg: row.avg,
-                max: row.max,
-                min: row.min,
-                entry_count: row.entry_count,
-                amount_count: row.amount_count,
-                suggested_price: row.suggested_price,
-                sale_avg: row.sale_avg,
-                sale_entry_count: row.sale_entry_count,
-                sale_amount_count: row.sale_amount_count,
-            },
-        });
+    if let Some(combined_path) = combined {
+        if options.format.writes_csv() {
+            let lookup_map: BTreeMap<String, String> = item_lookup.iter().map(|(id, name)| (id.to_string(), name.clone())).collect();
+            write_entries_to_combined_csv(&region_entries, &lookup_map, combined_path)?;
+        } else {
+            warn!("--combined is built from the per-region CSVs, which --format {:?} does not write; skipping it", options.format);
+        }
     }
 
-    Ok(entries)
+    Ok(())
 }
 
-pub fn total_average_market_cap(entries: &[ItemEntry]) -> u64 {
-    let total: f64 = entries
-        .iter()
-        .filter_map(|entry| {
-            let quantity = entry.price.amount_count;
-            if quantity == 0 {
-                return None;
+/// Regions are independent apart from the shared lookup CSV, which
+/// process_server serializes internally, so fetch them all at once.
+fn process_on_threads(regions: &[Region], options: &Options) -> Vec<(Region, thread::Result<io::Result<RegionOutput>>)> {
+    thread::scope(|scope| {
+        // With a delay the regions take turns, so only one download runs at
+        // a time, and with fail_fast a failure stops the ones after it.
+        if !options.region_delay.is_zero() {
+            let mut results = Vec::new();
+            for (index, &region) in regions.iter().enumerate() {
+                if index > 0 {
+                    info!("Waiting {}s before processing {}", options.region_delay.as_secs(), region);
+                    thread::sleep(options.region_delay);
+                }
+                let result = scope.spawn(move || process_server(region, options)).join();
+                let failed = !matches!(result, Ok(Ok(_)));
+                results.push((region, result));
+                if failed && options.fail_fast {
+                    break;
+                }
             }
-
-            let unit_price = entry
-                .price
-                .sale_avg
-                .unwrap_or(entry.price.avg)
-                .max(entry.price.min);
-
-            Some(unit_price * quantity as f64)
-        })
-        .sum();
-    total as u64
-}
-
-#[derive(Debug, Deserialize)]
-struct ItemNameRow {
-    item_id: u32,
-    item_name: String,
+            return results;
+        }
+        let handles: Vec<_> = regions
+            .iter()
+            .map(|&region| (region, scope.spawn(move || process_server(region, options))))
+            .collect();
+        handles
+            .into_iter()
+            .map(|(region, handle)| (region, handle.join()))
+            .collect()
+    })
 }
 
-pub fn load_item_names(path: &str) -> Result<HashMap<u32, String>, Box<dyn Error>> {
-    let file = File::open(path)?;
-    let mut reader = ReaderBuilder::new()
-        .has_headers(true)
-        .from_reader(file);
-
-    let mut names = HashMap::new();
-
-    for result in reader.deserialize::<ItemNameRow>() {
-        let row = result?;
-        names.insert(row.item_id, row.item_name);
+/// Takes an exclusive lock on `.ttcdata.lock` in `dir`, so a second run
+/// started while this one is still writing (e.g. an overlapping cron job)
+/// stops instead of clobbering the same files.
+fn lock_output_dir(dir: &Path) -> Result<File, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(".ttcdata.lock");
+    let file = File::options().create(true).write(true).truncate(false).open(&path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(format!("already running: another run holds {}", path.display()).into()),
+        Err(TryLockError::Error(e)) => Err(e.into()),
     }
-
-    Ok(names)
 }
 
-pub fn print_top_items_by_market_cap(entries: &[ItemEntry], item_names: &HashMap<u32, String>) {
-    let mut caps: HashMap<u32, f64> = HashMap::new();
-
-    for entry in entries {
-        let quantity = entry.price.amount_count;
-        let sellers = entry.price.sale_amount_count.unwrap_or(0);
-        if quantity <= 500 && sellers < 3 {
-            continue;
+/// Prints one line per region saying how its run ended.
+fn print_summary(regions: &[Region], outputs: &[RegionOutput], failures: &[(Region, String)], dry_run: bool) {
+    println!("\nSummary:");
+    for &region in regions {
+        if let Some(output) = outputs.iter().find(|output| output.region == region) {
+            let retries = match output.retries {
+                0 => String::new(),
+                1 => ", after 1 retry".to_string(),
+                n => format!(", after {} retries", n),
+            };
+            if output.unchanged {
+                println!("  {}: unchanged{}", region, retries);
+            } else {
+                let verb = if dry_run { "parsed" } else { "written" };
+                println!("  {}: {} entries {}{}", region, output.entries.to_formatted_string(&Locale::en), verb, retries);
+            }
+        } else if let Some((_, reason)) = failures.iter().find(|(failed, _)| *failed == region) {
+            println!("  {}: FAILED: {}", region, reason);
+        } else {
+            println!("  {}: skipped after an earlier failure", region);
         }
-
-        let unit_price = entry
-            .price
-            .sale_avg
-            .unwrap_or(entry.price.avg)
-            .max(entry.price.min);
-
-        let market_cap = unit_price * quantity as f64;
-
-        *caps.entry(entry.item_id.parse::<u32>().unwrap()).or_insert(0.0) += market_cap;
-    }
-
-    let mut caps_vec: Vec<(u32, f64)> = caps.into_iter().collect();
-
-    caps_vec.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-
-    println!("{:<6} {:<20} {}", "Rank", "Market Cap", "Item");
-
-    for (rank, (item_id, cap)) in caps_vec.into_iter().take(50).enumerate() {
-        let name = item_names
-            .get(&item_id)
-            .map(String::as_str)
-            .unwrap_or("UNKNOWN");
-
-        let cap_int = cap.round() as u64;
-        let formatted_cap = cap_int.to_formatted_string(&Locale::en);
-
-        println!(
-            "{:<6} {:<20} {}",
-            rank + 1,
-            formatted_cap,
-            name
-        );
     }
 }
 
-fn lookup_has_changed(new_map: &BTreeMap<String, String>, existing_path: &str) -> bool {
-    let Ok(existing) = load_item_names(existing_path) else {
-        return true;
-    };
-    if existing.len() != new_map.len() {
-        return true;
-    }
-    for (id, name) in new_map {
-        match existing.get(&id.parse::<u32>().unwrap_or(0)) {
-            Some(existing_name) if existing_name == name => {}
-            _ => return true,
-        }
-    }
-    false
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
 }
-
-fn main() -> Result<(), Box<dyn Error>> {
-    process_server("NA", "latest/na.csv")?;
-    process_server("EU", "latest/eu.csv")?;
-    let na = parse_items_from_csv_file("latest/na.csv")?;
-    let eu = parse_items_from_csv_file("latest/eu.csv")?;
-    let item_lookup = load_item_names("latest/lookup.csv")?;
-    let na_mc = total_average_market_cap(&na);
-    let eu_mc = total_average_market_cap(&eu);
-    println!("\nPC NA Gold Market Cap Estimate: {} (based on available TTC data)", na_mc.to_formatted_string(&Locale::en));
-    print_top_items_by_market_cap(&na, &item_lookup);
-    println!("\nPC EU Gold Market Cap Estimate: {} (based on available TTC data)", eu_mc.to_formatted_string(&Locale::en));
-    print_top_items_by_market_cap(&eu, &item_lookup);
-    Ok(())
-}
\ No newline at end of file
@@ -0,0 +1,183 @@
+use crate::{ItemEntry, PriceInfo, Region, ITEM_PATH_DEPTH};
+use csv::ReaderBuilder;
+use flate2::read::GzDecoder;
+use num_format::{Locale, ToFormattedString};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Opens a CSV written by this crate, decompressing it when the name ends
+/// in `.gz` or `.zst`.
+fn open_csv(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Ok(Box::new(GzDecoder::new(file))),
+        Some("zst") => Ok(Box::new(zstd::Decoder::new(file)?)),
+        _ => Ok(Box::new(file)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    item_id: String,
+    quality: String,
+    level: String,
+    #[serde(rename = "trait")]
+    trait_id: String,
+    variant: String,
+
+    avg: f64,
+    max: f64,
+    min: f64,
+    entry_count: u64,
+    amount_count: u64,
+
+    suggested_price: Option<f64>,
+    sale_avg: Option<f64>,
+    sale_entry_count: Option<u64>,
+    sale_amount_count: Option<u64>,
+
+    /// Only in CSVs written with `--region-column`, and the combined CSV.
+    #[serde(default)]
+    region: Option<Region>,
+}
+
+/// Reads a CSV or TSV written by this crate (per region, split part or
+/// combined, optionally `.gz`/`.zst` compressed) back into entries. Columns
+/// are matched by name, so `item_name`, `margin` and any missing optional
+/// column are ignored, and empty fields read as `None`.
+///
+/// Writing then reading gives back equal entries, except that prices keep
+/// only the four decimals the CSV has and `region` only survives with a
+/// region column.
+pub fn read_entries_from_csv(path: impl AsRef<Path>) -> Result<Vec<ItemEntry>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let file = open_csv(path)?;
+    let is_tsv = path.file_name().is_some_and(|name| name.to_string_lossy().contains(".tsv"));
+    let mut reader = ReaderBuilder::new().delimiter(if is_tsv { b'\t' } else { b',' }).from_reader(file);
+
+    let mut entries = Vec::new();
+
+    for result in reader.deserialize::<CsvRow>() {
+        let row = result?;
+
+        entries.push(ItemEntry {
+            item_id: row.item_id,
+            quality: row.quality,
+            level: row.level,
+            trait_id: row.trait_id,
+            variant: row.variant,
+            depth: ITEM_PATH_DEPTH,
+            region: row.region,
+            price: PriceInfo {
+                avg: row.avg,
+                max: row.max,
+                min: row.min,
+                entry_count: row.entry_count,
+                amount_count: row.amount_count,
+                suggested_price: row.suggested_price,
+                sale_avg: row.sale_avg,
+                sale_entry_count: row.sale_entry_count,
+                sale_amount_count: row.sale_amount_count,
+            },
+        });
+    }
+
+    Ok(entries)
+}
+
+#[deprecated(note = "renamed to read_entries_from_csv")]
+pub fn parse_items_from_csv_file(path: impl AsRef<Path>) -> Result<Vec<ItemEntry>, Box<dyn Error>> {
+    read_entries_from_csv(path)
+}
+
+pub fn total_average_market_cap(entries: &[ItemEntry]) -> u64 {
+    let total: f64 = entries
+        .iter()
+        .filter_map(|entry| {
+            let quantity = entry.price.amount_count;
+            if quantity == 0 {
+                return None;
+            }
+
+            let unit_price = entry
+                .price
+                .sale_avg
+                .unwrap_or(entry.price.avg)
+                .max(entry.price.min);
+
+            Some(unit_price * quantity as f64)
+        })
+        .sum();
+    total as u64
+}
+
+#[derive(Debug, Deserialize)]
+struct ItemNameRow {
+    item_id: u32,
+    item_name: String,
+}
+
+pub fn load_item_names(path: impl AsRef<Path>) -> Result<HashMap<u32, String>, Box<dyn Error>> {
+    let file = open_csv(path.as_ref())?;
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(file);
+
+    let mut names = HashMap::new();
+
+    for result in reader.deserialize::<ItemNameRow>() {
+        let row = result?;
+        names.insert(row.item_id, row.item_name);
+    }
+
+    Ok(names)
+}
+
+pub fn print_top_items_by_market_cap(entries: &[ItemEntry], item_names: &HashMap<u32, String>) {
+    let mut caps: HashMap<u32, f64> = HashMap::new();
+
+    for entry in entries {
+        let quantity = entry.price.amount_count;
+        let sellers = entry.price.sale_amount_count.unwrap_or(0);
+        if quantity <= 500 && sellers < 3 {
+            continue;
+        }
+
+        let unit_price = entry
+            .price
+            .sale_avg
+            .unwrap_or(entry.price.avg)
+            .max(entry.price.min);
+
+        let market_cap = unit_price * quantity as f64;
+
+        *caps.entry(entry.item_id.parse::<u32>().unwrap()).or_insert(0.0) += market_cap;
+    }
+
+    let mut caps_vec: Vec<(u32, f64)> = caps.into_iter().collect();
+
+    caps_vec.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+    println!("{:<6} {:<20} Item", "Rank", "Market Cap");
+
+    for (rank, (item_id, cap)) in caps_vec.into_iter().take(50).enumerate() {
+        let name = item_names
+            .get(&item_id)
+            .map(String::as_str)
+            .unwrap_or("UNKNOWN");
+
+        let cap_int = cap.round() as u64;
+        let formatted_cap = cap_int.to_formatted_string(&Locale::en);
+
+        println!(
+            "{:<6} {:<20} {}",
+            rank + 1,
+            formatted_cap,
+            name
+        );
+    }
+}
@@ -0,0 +1,508 @@
+use crate::parse::for_each_ttc_entry;
+use crate::temp::TempFile;
+use chrono::{DateTime, Datelike, TimeZone};
+use crate::{ItemEntry, OutputFile, ParseStats, Region, RegionOutput};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+/// Which writers `process_server` runs for the price entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    Both,
+    /// One JSON object per line, for streaming consumers.
+    Ndjson,
+    /// The CSV columns separated by tabs instead of commas.
+    Tsv,
+    /// Typed columnar file for analytics tools.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl OutputFormat {
+    pub fn writes_csv(self) -> bool {
+        matches!(self, OutputFormat::Csv | OutputFormat::Both)
+    }
+
+    pub fn writes_json(self) -> bool {
+        matches!(self, OutputFormat::Json | OutputFormat::Both)
+    }
+
+    pub fn writes_ndjson(self) -> bool {
+        matches!(self, OutputFormat::Ndjson)
+    }
+
+    pub fn writes_tsv(self) -> bool {
+        matches!(self, OutputFormat::Tsv)
+    }
+
+    #[cfg(feature = "parquet")]
+    pub fn writes_parquet(self) -> bool {
+        matches!(self, OutputFormat::Parquet)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "both" => Ok(OutputFormat::Both),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "tsv" => Ok(OutputFormat::Tsv),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(OutputFormat::Parquet),
+            _ => Err(format!("unknown format '{}' (expected {})", s, EXPECTED_FORMATS)),
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+const EXPECTED_FORMATS: &str = "csv, json, both, ndjson, tsv or parquet";
+#[cfg(not(feature = "parquet"))]
+const EXPECTED_FORMATS: &str = "csv, json, both, ndjson or tsv";
+
+const ENTRY_HEADER: [&str; 16] = [
+    "item_id", "quality", "level", "trait", "variant",
+    "avg", "max", "min", "entry_count", "amount_count",
+    "suggested_price", "sale_avg", "sale_entry_count", "sale_amount_count",
+    "item_name", "margin",
+];
+
+/// Type of each [`ENTRY_HEADER`] column, after the `ItemEntry` and
+/// `PriceInfo` fields it comes from, and whether the column can be empty.
+/// `item_name` is empty for items the lookup table has no name for.
+const ENTRY_TYPES: [(&str, bool); 16] = [
+    ("string", false), ("string", false), ("string", false), ("string", false), ("string", false),
+    ("float", false), ("float", false), ("float", false), ("integer", false), ("integer", false),
+    ("float", true), ("float", true), ("integer", true), ("integer", true),
+    ("string", true), ("float", true),
+];
+
+/// Where a region's dated files go, relative to the output directory and
+/// without the extension each format adds. Placeholders: `{region}` (`na`,
+/// `eu`), `{date}` (`2026-03-28`), `{year}`, `{month}`, `{day}` and
+/// `{timestamp}` (Unix seconds). Dates are in the run's timezone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    /// Renders the template for `region`'s table captured at `time`.
+    pub fn render<Tz: TimeZone>(&self, region: Region, time: &DateTime<Tz>) -> PathBuf {
+        let rendered = self
+            .0
+            .replace("{region}", region.csv_prefix())
+            .replace("{date}", &format!("{:04}-{:02}-{:02}", time.year(), time.month(), time.day()))
+            .replace("{year}", &format!("{:04}", time.year()))
+            .replace("{month}", &format!("{:02}", time.month()))
+            .replace("{day}", &format!("{:02}", time.day()))
+            .replace("{timestamp}", &time.timestamp().to_string());
+        PathBuf::from(rendered)
+    }
+}
+
+impl Default for NameTemplate {
+    /// `2026/03/28/na`, the layout used before templates existed.
+    fn default() -> Self {
+        NameTemplate("{year}/{month}/{day}/{region}".to_string())
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const PLACEHOLDERS: [&str; 6] = ["region", "date", "year", "month", "day", "timestamp"];
+        let mut rest = s;
+        while let Some(open) = rest.find('{') {
+            let Some(close) = rest[open..].find('}') else {
+                return Err(format!("unclosed '{{' in name template '{}'", s));
+            };
+            let name = &rest[open + 1..open + close];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!("unknown placeholder {{{}}} (expected {{{}}})", name, PLACEHOLDERS.join("}, {")));
+            }
+            rest = &rest[open + close + 1..];
+        }
+        // Without the region both regions would write the same files.
+        if !s.contains("{region}") {
+            return Err("name template must contain {region}".to_string());
+        }
+        if s.ends_with('/') || s.starts_with('/') {
+            return Err("name template must be a relative file name, not a directory".to_string());
+        }
+        Ok(NameTemplate(s.to_string()))
+    }
+}
+
+/// A subset of the CSV columns, in the order they were listed, e.g.
+/// `item_id,avg,suggested_price`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Columns(Vec<usize>);
+
+impl FromStr for Columns {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut indices = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match ENTRY_HEADER.iter().position(|column| column.eq_ignore_ascii_case(name)) {
+                Some(index) => indices.push(index),
+                None => return Err(format!("unknown column '{}' (expected any of {})", name, ENTRY_HEADER.join(", "))),
+            }
+        }
+        if indices.is_empty() {
+            return Err("no columns given".to_string());
+        }
+        Ok(Columns(indices))
+    }
+}
+
+/// Decimal places kept for prices in the CSV; finer than any gold amount.
+const PRICE_DECIMALS: usize = 4;
+
+/// Formats a price for the CSV with at most [`PRICE_DECIMALS`] decimals and
+/// no trailing zeros, so float noise such as `0.30000000000000004` is
+/// written as `0.3`.
+pub fn format_price(value: f64) -> String {
+    let text = format!("{:.*}", PRICE_DECIMALS, value);
+    let text = if text.contains('.') { text.trim_end_matches('0').trim_end_matches('.') } else { &text };
+    if text == "-0" { "0".to_string() } else { text.to_string() }
+}
+
+fn entry_record(e: &ItemEntry, lookup_map: &BTreeMap<String, String>) -> [String; 16] {
+    [
+        e.item_id.clone(),
+        e.quality.clone(),
+        e.level.clone(),
+        e.trait_id.clone(),
+        e.variant.clone(),
+        format_price(e.price.avg),
+        format_price(e.price.max),
+        format_price(e.price.min),
+        e.price.entry_count.to_string(),
+        e.price.amount_count.to_string(),
+        e.price.suggested_price.map_or("".to_string(), format_price),
+        e.price.sale_avg.map_or("".to_string(), format_price),
+        e.price.sale_entry_count.map_or("".to_string(), |v| v.to_string()),
+        e.price.sale_amount_count.map_or("".to_string(), |v| v.to_string()),
+        lookup_map.get(&e.item_id).cloned().unwrap_or_default(),
+        e.price.margin().map_or("".to_string(), format_price),
+    ]
+}
+
+/// Writes the entries as delimited text: `b','` for CSV, `b'\t'` for TSV.
+/// Fields holding the delimiter, a quote or a newline are quoted either way.
+/// `region_column` adds a leading `region` column, as in the combined CSV.
+fn write_entries_csv<W: Write>(
+    entries: &[ItemEntry],
+    lookup_map: &BTreeMap<String, String>,
+    columns: Option<&Columns>,
+    delimiter: u8,
+    region_column: bool,
+    writer: W,
+) -> io::Result<()> {
+    let mut wtr = csv::WriterBuilder::new().delimiter(delimiter).from_writer(writer);
+    let all_columns: Vec<usize> = (0..ENTRY_HEADER.len()).collect();
+    let columns = columns.map_or(&all_columns[..], |columns| &columns.0[..]);
+    let region = region_column.then_some("region");
+    wtr.write_record(region.into_iter().chain(columns.iter().map(|&i| ENTRY_HEADER[i])))?;
+    for e in entries {
+        let record = entry_record(e, lookup_map);
+        let region = region_column.then(|| e.region.map_or("", Region::csv_prefix));
+        wtr.write_record(region.into_iter().chain(columns.iter().map(|&i| record[i].as_str())))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn write_combined_csv<W: Write>(regions: &[(Region, Vec<ItemEntry>)], lookup_map: &BTreeMap<String, String>, writer: W) -> io::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(std::iter::once("region").chain(ENTRY_HEADER))?;
+    for (region, entries) in regions {
+        for e in entries {
+            wtr.write_record(std::iter::once(region.csv_prefix().to_string()).chain(entry_record(e, lookup_map)))?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn write_lookup_csv<W: Write>(lookup_map: &BTreeMap<String, String>, writer: W) -> io::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(["item_id", "item_name"])?;
+    for (id, name) in lookup_map.iter() {
+        wtr.write_record([id, name])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes through a temporary sibling file that is renamed over `path` only
+/// once `write` succeeds, so a reader never sees a half-written file.
+pub(crate) fn write_atomically(path: &Path, write: impl FnOnce(File) -> io::Result<()>) -> io::Result<()> {
+    if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+    let file_name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    // Dropping the guard removes the temporary file unless it was renamed.
+    let tmp_path = TempFile::new(path.with_file_name(format!(".{}.{}.tmp", file_name, process::id())));
+    File::create(&tmp_path).and_then(write)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Runs `write` against `file`, gzip-compressing the output when `path`
+/// ends in `.gz`.
+fn write_maybe_gzip(path: &Path, file: File, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    let mut writer = BufWriter::new(file);
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        write(&mut encoder)?;
+        encoder.finish()?.flush()
+    } else {
+        write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+pub(crate) fn write_entries_to_csv_manual(
+    entries: &[ItemEntry],
+    lookup_map: &BTreeMap<String, String>,
+    columns: Option<&Columns>,
+    delimiter: u8,
+    region_column: bool,
+    path: &Path,
+) -> std::io::Result<()> {
+    write_atomically(path, |file| write_maybe_gzip(path, file, |w| write_entries_csv(entries, lookup_map, columns, delimiter, region_column, w)))
+}
+
+/// Parses `lua_text` and writes each entry to `writer` as a CSV row as soon
+/// as it is found, without collecting the table first. Unlike
+/// [`parse_ttc_lua`](crate::parse_ttc_lua), rows are neither deduplicated
+/// nor sorted by identity.
+pub fn write_ttc_lua_to_csv<W: Write>(lua_text: &str, lookup_map: &BTreeMap<String, String>, writer: W) -> io::Result<ParseStats> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(ENTRY_HEADER)?;
+    // The walk cannot be stopped from the callback, so keep the first write
+    // error and skip the remaining rows.
+    let mut written: csv::Result<()> = Ok(());
+    let (_, stats) = for_each_ttc_entry(lua_text, |entry| {
+        if written.is_ok() {
+            written = wtr.write_record(entry_record(&entry, lookup_map));
+        }
+    })
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    written?;
+    wtr.flush()?;
+    Ok(stats)
+}
+
+/// Writes the entries of every region to one CSV, with a leading `region`
+/// column (`na`/`eu`) in front of the usual columns.
+pub fn write_entries_to_combined_csv(regions: &[(Region, Vec<ItemEntry>)], lookup_map: &BTreeMap<String, String>, path: &Path) -> io::Result<()> {
+    write_atomically(path, |file| write_combined_csv(regions, lookup_map, file))
+}
+
+pub(crate) fn write_entries_to_json(entries: &[ItemEntry], path: &Path) -> io::Result<()> {
+    write_atomically(path, |file| write_maybe_gzip(path, file, |w| Ok(serde_json::to_writer(w, entries)?)))
+}
+
+/// An entry with its item name joined in, as written on each NDJSON line.
+#[derive(Serialize)]
+struct NdjsonRecord<'a> {
+    #[serde(flatten)]
+    entry: &'a ItemEntry,
+    item_name: Option<&'a str>,
+    margin: Option<f64>,
+}
+
+pub(crate) fn write_entries_to_ndjson(entries: &[ItemEntry], lookup_map: &BTreeMap<String, String>, path: &Path) -> io::Result<()> {
+    write_atomically(path, |file| {
+        write_maybe_gzip(path, file, |w| {
+            for entry in entries {
+                let item_name = lookup_map.get(&entry.item_id).map(String::as_str);
+                let record = NdjsonRecord { entry, item_name, margin: entry.price.margin() };
+                serde_json::to_writer(&mut *w, &record)?;
+                w.write_all(b"\n")?;
+            }
+            Ok(())
+        })
+    })
+}
+
+pub(crate) fn write_entries_to_csv_zst(
+    entries: &[ItemEntry],
+    lookup_map: &BTreeMap<String, String>,
+    columns: Option<&Columns>,
+    delimiter: u8,
+    region_column: bool,
+    path: &Path,
+) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = File::create(path)?;
+    let mut encoder = zstd::Encoder::new(BufWriter::new(file), 3)?;
+    write_entries_csv(entries, lookup_map, columns, delimiter, region_column, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+pub(crate) fn write_lookup_table(lookup_map: &BTreeMap<String, String>, path: &Path) -> std::io::Result<()> {
+    write_atomically(path, |file| write_maybe_gzip(path, file, |w| write_lookup_csv(lookup_map, w)))
+}
+
+pub(crate) fn write_lookup_table_zst(lookup_map: &BTreeMap<String, String>, path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = File::create(path)?;
+    let mut encoder = zstd::Encoder::new(BufWriter::new(file), 3)?;
+    write_lookup_csv(lookup_map, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+/// Headline numbers for one price table, cheap to alert on when a run
+/// suddenly yields far fewer entries than usual.
+#[derive(Debug, Serialize)]
+struct Summary {
+    entries: usize,
+    unique_item_ids: usize,
+    min_avg: Option<f64>,
+    max_avg: Option<f64>,
+    median_avg: Option<f64>,
+    with_suggested_price: usize,
+    timestamp: Option<i64>,
+    /// What the prices were multiplied by; 1 for prices per unit.
+    price_multiplier: u32,
+}
+
+impl Summary {
+    fn new(entries: &[ItemEntry], timestamp: Option<i64>, price_multiplier: u32) -> Self {
+        let mut avgs: Vec<f64> = entries.iter().map(|e| e.price.avg).collect();
+        avgs.sort_by(f64::total_cmp);
+        let median_avg = match avgs.len() {
+            0 => None,
+            n if n % 2 == 1 => Some(avgs[n / 2]),
+            n => Some((avgs[n / 2 - 1] + avgs[n / 2]) / 2.0),
+        };
+        Summary {
+            entries: entries.len(),
+            unique_item_ids: entries.iter().map(|e| e.item_id.as_str()).collect::<HashSet<_>>().len(),
+            min_avg: avgs.first().copied(),
+            max_avg: avgs.last().copied(),
+            median_avg,
+            with_suggested_price: entries.iter().filter(|e| e.price.suggested_price.is_some()).count(),
+            timestamp,
+            price_multiplier,
+        }
+    }
+}
+
+pub(crate) fn write_summary(entries: &[ItemEntry], timestamp: Option<i64>, price_multiplier: u32, path: &Path) -> io::Result<()> {
+    write_atomically(path, |file| {
+        write_maybe_gzip(path, file, |w| Ok(serde_json::to_writer_pretty(w, &Summary::new(entries, timestamp, price_multiplier))?))
+    })
+}
+
+/// Writes one row per `quality` tier: how many entries it has, their mean
+/// `avg` price and their total `amount_count`.
+pub(crate) fn write_quality_stats(entries: &[ItemEntry], path: &Path) -> io::Result<()> {
+    let mut tiers: BTreeMap<&str, (usize, f64, u64)> = BTreeMap::new();
+    for e in entries {
+        let tier = tiers.entry(&e.quality).or_default();
+        tier.0 += 1;
+        tier.1 += e.price.avg;
+        tier.2 += e.price.amount_count;
+    }
+    write_atomically(path, |file| {
+        write_maybe_gzip(path, file, |w| {
+            let mut wtr = csv::Writer::from_writer(w);
+            wtr.write_record(["quality", "entries", "mean_avg", "total_amount_count"])?;
+            for (quality, (count, avg_sum, amount)) in tiers {
+                wtr.write_record([quality.to_string(), count.to_string(), format_price(avg_sum / count as f64), amount.to_string()])?;
+            }
+            wtr.flush()
+        })
+    })
+}
+
+#[derive(Serialize)]
+struct Schema {
+    columns: Vec<SchemaColumn>,
+}
+
+#[derive(Serialize)]
+struct SchemaColumn {
+    name: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    nullable: bool,
+}
+
+/// Writes the type of every column of the CSV and TSV, in order, so a
+/// DataFrame loader does not read them all as strings. IDs stay strings as
+/// in `ItemEntry`; empty fields of a nullable column are missing values.
+pub(crate) fn write_schema(columns: Option<&Columns>, region_column: bool, path: &Path) -> io::Result<()> {
+    let all_columns: Vec<usize> = (0..ENTRY_HEADER.len()).collect();
+    let columns = columns.map_or(&all_columns[..], |columns| &columns.0[..]);
+    let region = region_column.then_some(SchemaColumn { name: "region", kind: "string", nullable: false });
+    let schema = Schema {
+        columns: region
+            .into_iter()
+            .chain(columns.iter().map(|&i| SchemaColumn { name: ENTRY_HEADER[i], kind: ENTRY_TYPES[i].0, nullable: ENTRY_TYPES[i].1 }))
+            .collect(),
+    };
+    write_atomically(path, |file| Ok(serde_json::to_writer_pretty(file, &schema)?))
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    /// Unix time the manifest was written.
+    generated_at: i64,
+    regions: &'a [RegionOutput],
+}
+
+/// The part of an existing manifest [`write_manifest`] compares against.
+#[derive(Deserialize)]
+struct WrittenManifest {
+    regions: Vec<WrittenRegion>,
+}
+
+#[derive(Deserialize)]
+struct WrittenRegion {
+    files: Vec<OutputFile>,
+}
+
+/// Lists every file a run wrote, per region, for downstream tooling. When
+/// the existing manifest already lists every file with the same contents,
+/// it is left alone, so `generated_at` only moves when the outputs do.
+pub fn write_manifest(outputs: &[RegionOutput], path: &Path) -> io::Result<()> {
+    let written: Vec<&OutputFile> = outputs.iter().flat_map(|output| &output.files).collect();
+    let existing = fs::read(path).ok().and_then(|bytes| serde_json::from_slice::<WrittenManifest>(&bytes).ok());
+    if let Some(existing) = existing {
+        let listed: HashSet<(&Path, &str)> =
+            existing.regions.iter().flat_map(|region| &region.files).map(|file| (file.path.as_path(), file.sha256.as_str())).collect();
+        if written.iter().all(|file| listed.contains(&(file.path.as_path(), file.sha256.as_str()))) {
+            debug!("{} already lists every output unchanged; leaving it alone", path.display());
+            return Ok(());
+        }
+    }
+    let manifest = Manifest { generated_at: chrono::Utc::now().timestamp(), regions: outputs };
+    write_atomically(path, |file| {
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &manifest)?;
+        writer.flush()
+    })
+}
@@ -0,0 +1,112 @@
+//! `parquet` feature: writes price entries as a typed Parquet file with the
+//! same columns as the CSV output.
+
+use crate::output::write_atomically;
+use crate::ItemEntry;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, DoubleType, Int64Type};
+use parquet::errors::Result;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use parquet::schema::parser::parse_message_type;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+const SCHEMA: &str = "
+message item_entry {
+    REQUIRED BYTE_ARRAY item_id (UTF8);
+    REQUIRED BYTE_ARRAY quality (UTF8);
+    REQUIRED BYTE_ARRAY level (UTF8);
+    REQUIRED BYTE_ARRAY trait (UTF8);
+    REQUIRED BYTE_ARRAY variant (UTF8);
+    REQUIRED DOUBLE avg;
+    REQUIRED DOUBLE max;
+    REQUIRED DOUBLE min;
+    REQUIRED INT64 entry_count (INTEGER(64, false));
+    REQUIRED INT64 amount_count (INTEGER(64, false));
+    OPTIONAL DOUBLE suggested_price;
+    OPTIONAL DOUBLE sale_avg;
+    OPTIONAL INT64 sale_entry_count (INTEGER(64, false));
+    OPTIONAL INT64 sale_amount_count (INTEGER(64, false));
+    OPTIONAL BYTE_ARRAY item_name (UTF8);
+    OPTIONAL DOUBLE margin;
+}
+";
+
+/// Rows per row group; keeps memory flat on the full tables.
+const ROW_GROUP_SIZE: usize = 128 * 1024;
+
+pub(crate) fn write_entries_to_parquet(entries: &[ItemEntry], lookup_map: &BTreeMap<String, String>, path: &Path) -> io::Result<()> {
+    write_atomically(path, |file| write(entries, lookup_map, file).map_err(io::Error::other))
+}
+
+fn write(entries: &[ItemEntry], lookup_map: &BTreeMap<String, String>, file: File) -> Result<()> {
+    let schema = Arc::new(parse_message_type(SCHEMA)?);
+    let properties = Arc::new(WriterProperties::builder().set_compression(Compression::ZSTD(Default::default())).build());
+    let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+
+    for rows in entries.chunks(ROW_GROUP_SIZE) {
+        let mut row_group = writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            write_column(&mut column, index, rows, lookup_map)?;
+            column.close()?;
+            index += 1;
+        }
+        row_group.close()?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+/// Writes column `index` of [`SCHEMA`] for `rows`.
+fn write_column(column: &mut SerializedColumnWriter<'_>, index: usize, rows: &[ItemEntry], lookup_map: &BTreeMap<String, String>) -> Result<()> {
+    // Counts are stored as INT64 with an unsigned logical type, so the bit
+    // pattern of the u64 is kept as is.
+    let count = |n: u64| n as i64;
+    match index {
+        0..=4 => {
+            let values: Vec<ByteArray> = rows
+                .iter()
+                .map(|e| {
+                    let (item_id, quality, level, trait_id, variant) = e.identity();
+                    ByteArray::from([item_id, quality, level, trait_id, variant][index])
+                })
+                .collect();
+            column.typed::<ByteArrayType>().write_batch(&values, None, None)?;
+        }
+        5 => required::<DoubleType>(column, rows.iter().map(|e| e.price.avg).collect())?,
+        6 => required::<DoubleType>(column, rows.iter().map(|e| e.price.max).collect())?,
+        7 => required::<DoubleType>(column, rows.iter().map(|e| e.price.min).collect())?,
+        8 => required::<Int64Type>(column, rows.iter().map(|e| count(e.price.entry_count)).collect())?,
+        9 => required::<Int64Type>(column, rows.iter().map(|e| count(e.price.amount_count)).collect())?,
+        10 => optional::<DoubleType>(column, rows.iter().map(|e| e.price.suggested_price))?,
+        11 => optional::<DoubleType>(column, rows.iter().map(|e| e.price.sale_avg))?,
+        12 => optional::<Int64Type>(column, rows.iter().map(|e| e.price.sale_entry_count.map(count)))?,
+        13 => optional::<Int64Type>(column, rows.iter().map(|e| e.price.sale_amount_count.map(count)))?,
+        14 => optional::<ByteArrayType>(column, rows.iter().map(|e| lookup_map.get(&e.item_id).map(|name| ByteArray::from(name.as_str()))))?,
+        _ => optional::<DoubleType>(column, rows.iter().map(|e| e.price.margin()))?,
+    }
+    Ok(())
+}
+
+fn required<T: DataType>(column: &mut SerializedColumnWriter<'_>, values: Vec<T::T>) -> Result<()> {
+    column.typed::<T>().write_batch(&values, None, None)?;
+    Ok(())
+}
+
+/// Writes the present values plus a definition level per row marking which
+/// rows are null.
+fn optional<T: DataType>(column: &mut SerializedColumnWriter<'_>, values: impl Iterator<Item = Option<T::T>>) -> Result<()> {
+    let mut present = Vec::new();
+    let mut levels = Vec::new();
+    for value in values {
+        levels.push(i16::from(value.is_some()));
+        present.extend(value);
+    }
+    column.typed::<T>().write_batch(&present, Some(&levels), None)?;
+    Ok(())
+}
@@ -0,0 +1,842 @@
+use crate::Region;
+use chrono::{DateTime, Utc};
+use log::{debug, info, trace, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::panic;
+
+/// Price statistics for a single item variant, as published by TTC.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PriceInfo {
+    /// Average listed unit price.
+    #[serde(rename(deserialize = "A"))]
+    pub avg: f64,
+    /// Highest listed unit price.
+    #[serde(rename(deserialize = "X"))]
+    pub max: f64,
+    /// Lowest listed unit price.
+    #[serde(rename(deserialize = "N"))]
+    pub min: f64,
+    /// Number of listings seen.
+    #[serde(rename(deserialize = "EC"))]
+    pub entry_count: u64,
+    /// Total quantity across all listings.
+    #[serde(rename(deserialize = "AC"))]
+    pub amount_count: u64,
+    /// TTC suggested unit price, when available.
+    #[serde(rename(deserialize = "S"))]
+    pub suggested_price: Option<f64>,
+    /// Average unit price of recorded sales.
+    #[serde(rename(deserialize = "SA"))]
+    pub sale_avg: Option<f64>,
+    /// Number of recorded sales.
+    #[serde(rename(deserialize = "SE"))]
+    pub sale_entry_count: Option<u64>,
+    /// Total quantity across recorded sales.
+    #[serde(rename(deserialize = "SAC"))]
+    pub sale_amount_count: Option<u64>,
+}
+
+impl PriceInfo {
+    /// How far the suggested price sits above the average listing,
+    /// `(suggested_price - avg) / avg`. `None` without a suggested price or
+    /// when `avg` is zero.
+    pub fn margin(&self) -> Option<f64> {
+        let suggested = self.suggested_price?;
+        (self.avg != 0.0).then(|| (suggested - self.avg) / self.avg)
+    }
+
+    /// Multiplies every price by `factor`, e.g. 200 for gold per stack of
+    /// 200. Counts are left alone.
+    pub fn scale_prices(&mut self, factor: f64) {
+        self.avg *= factor;
+        self.max *= factor;
+        self.min *= factor;
+        for price in [&mut self.suggested_price, &mut self.sale_avg].into_iter().flatten() {
+            *price *= factor;
+        }
+    }
+
+    /// Whether the prices are consistent: `0 <= min <= avg <= max`, which
+    /// also rules out NaN. Counts need no check, as a negative count already
+    /// fails to deserialize into the unsigned fields.
+    pub fn is_sane(&self) -> bool {
+        0.0 <= self.min && self.min <= self.avg && self.avg <= self.max
+    }
+}
+
+/// A price entry keyed by the path it was found at in the `Data` table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ItemEntry {
+    /// TTC item id.
+    pub item_id: String,
+    /// Item quality tier.
+    pub quality: String,
+    /// Item level as encoded by TTC.
+    pub level: String,
+    /// Item trait id, `-1` when the item has no trait.
+    #[serde(rename = "trait")]
+    pub trait_id: String,
+    /// Variant key as encoded by TTC.
+    pub variant: String,
+    /// Number of keys between `Data` and the price node. Anything other than
+    /// [`ITEM_PATH_DEPTH`] means the identity fields could not be trusted and
+    /// only `item_id` was filled in.
+    #[serde(skip)]
+    pub depth: usize,
+    /// Server the prices were published for. `None` from [`parse_ttc_lua`],
+    /// which only sees the Lua text; [`process_server`](crate::process_server)
+    /// fills it in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    /// Price statistics for this entry.
+    #[serde(flatten)]
+    pub price: PriceInfo,
+}
+
+impl ItemEntry {
+    /// The item id, quality, level, trait and variant that identify this entry.
+    pub fn identity(&self) -> (&str, &str, &str, &str, &str) {
+        (&self.item_id, &self.quality, &self.level, &self.trait_id, &self.variant)
+    }
+}
+
+/// A parsed `PriceTableXX.lua` file.
+#[derive(Debug, Clone)]
+pub struct PriceTable {
+    /// Price entries sorted by identity, with duplicates collapsed.
+    pub entries: Vec<ItemEntry>,
+    /// The table's `TimeStamp`, when present and plausible.
+    pub timestamp: Option<i64>,
+    /// `timestamp` as a UTC date and time.
+    pub captured_at: Option<DateTime<Utc>>,
+    /// How much of the `Data` table turned into entries.
+    pub stats: ParseStats,
+}
+
+/// Coverage counters from walking the `Data` table, for spotting a layout
+/// change before it shows up as a drop in entry counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseStats {
+    /// Tables walked, not counting price nodes.
+    pub objects_visited: usize,
+    /// Price nodes turned into entries, before duplicates are dropped.
+    pub price_nodes: usize,
+    /// Non-table values and unreadable price nodes that were ignored.
+    pub nodes_skipped: usize,
+    /// Tables holding some [`PriceInfo`] keys but not all of
+    /// [`PRICE_NODE_KEYS`], walked as plain tables instead.
+    pub partial_price_nodes: usize,
+}
+
+impl ParseStats {
+    /// Share of leaf nodes that were skipped rather than parsed.
+    pub fn skipped_ratio(&self) -> f64 {
+        let leaves = self.price_nodes + self.nodes_skipped;
+        if leaves == 0 { 0.0 } else { self.nodes_skipped as f64 / leaves as f64 }
+    }
+}
+
+/// Skipping more than this share of leaves is logged as a likely format change.
+const SKIPPED_WARN_RATIO: f64 = 0.05;
+
+impl PriceTable {
+    /// Number of price entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the table holds no price entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the price entries.
+    pub fn iter(&self) -> std::slice::Iter<'_, ItemEntry> {
+        self.entries.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a PriceTable {
+    type Item = &'a ItemEntry;
+    type IntoIter = std::slice::Iter<'a, ItemEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Expected path depth of a price node: item id, quality, level, trait and variant.
+pub const ITEM_PATH_DEPTH: usize = 5;
+
+/// Errors raised while locating the price table in a TTC Lua file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// No `self.PriceTable = {` assignment was found.
+    MarkerNotFound,
+    /// The table ended before its braces were closed.
+    UnbalancedBraces,
+    /// The table ended inside a string literal.
+    UnterminatedString,
+    /// The Lua parser backend rejected the table.
+    InvalidLua(String),
+    /// The regex pipeline turned the table into invalid JSON. `line` and
+    /// `column` point into that generated JSON, and `snippet` is the text
+    /// around them.
+    InvalidJson { message: String, line: usize, column: usize, snippet: String },
+    /// The table has no `Data` table holding the price entries.
+    MissingData,
+    /// A price node at `Data/{path}` that does not read as [`PriceInfo`].
+    /// Only [`entries_iter`] yields this; the other parsers log and skip it.
+    InvalidPriceNode { path: String, message: String },
+    /// The parser panicked; only returned by [`try_parse_ttc_lua`].
+    Internal(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MarkerNotFound => write!(f, "could not locate self.PriceTable={{...}} block"),
+            ParseError::UnbalancedBraces => write!(f, "unbalanced braces in self.PriceTable block"),
+            ParseError::UnterminatedString => write!(f, "unterminated string in self.PriceTable block"),
+            ParseError::InvalidLua(message) => write!(f, "invalid Lua in self.PriceTable block: {}", message),
+            ParseError::InvalidJson { message, line, column, snippet } => {
+                write!(f, "self.PriceTable block is not convertible to JSON: {} at line {} column {}, near `{}`", message, line, column, snippet)
+            }
+            ParseError::MissingData => write!(f, "self.PriceTable block has no Data table"),
+            ParseError::InvalidPriceNode { path, message } => write!(f, "unreadable price node at Data/{}: {}", path, message),
+            ParseError::Internal(message) => write!(f, "price table parser panicked: {}", message),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+const PRICE_TABLE_MARKER: &str = "self.PriceTable";
+
+/// Index of the `{` when `bytes[idx..]` reads `= {`, allowing whitespace
+/// around the `=`.
+fn table_assignment_brace(bytes: &[u8], mut idx: usize) -> Option<usize> {
+    let skip_whitespace = |mut idx: usize| {
+        while bytes.get(idx).is_some_and(u8::is_ascii_whitespace) {
+            idx += 1;
+        }
+        idx
+    };
+    idx = skip_whitespace(idx);
+    if bytes.get(idx) != Some(&b'=') || bytes.get(idx + 1) == Some(&b'=') {
+        return None;
+    }
+    idx = skip_whitespace(idx + 1);
+    (bytes.get(idx) == Some(&b'{')).then_some(idx)
+}
+
+pub(crate) fn extract_price_table(text: &str) -> Result<String, ParseError> {
+    // Every delimiter we care about is ASCII, so scanning bytes is safe and
+    // the indices we find are always valid char boundaries for slicing.
+    let bytes = text.as_bytes();
+    let mut table = String::new();
+    let mut marker_found = false;
+    let mut depth: i32 = 0;
+    let mut in_string: Option<u8> = None;
+    let mut escaped = false;
+    // Start of the table text not yet copied into `table`; comments inside
+    // the table are dropped by flushing up to them and skipping past.
+    let mut segment_start = 0;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let c = bytes[idx];
+        if in_string.is_some() {
+            if escaped {
+                escaped = false;
+            } else if c == b'\\' {
+                escaped = true;
+            } else if Some(c) == in_string {
+                in_string = None;
+            }
+        } else if c == b'-' && bytes.get(idx + 1) == Some(&b'-') {
+            let end = match long_bracket_level(bytes, idx + 2) {
+                Some(level) => find_long_bracket_close(bytes, idx + 2, level).unwrap_or(bytes.len()),
+                None => text[idx..].find('\n').map_or(bytes.len(), |n| idx + n),
+            };
+            if depth > 0 {
+                table.push_str(&text[segment_start..idx]);
+                segment_start = end;
+            }
+            idx = end;
+            continue;
+        } else if c == b'\'' || c == b'"' {
+            in_string = Some(c);
+        } else if let Some(level) = long_bracket_level(bytes, idx) {
+            // Long strings stay in the table text; lua_to_json converts them.
+            idx = find_long_bracket_close(bytes, idx, level).ok_or(ParseError::UnterminatedString)?;
+            continue;
+        } else if !marker_found {
+            if text[idx..].starts_with(PRICE_TABLE_MARKER) {
+                idx += PRICE_TABLE_MARKER.len();
+                // Only `self.PriceTable = {` starts the table; other uses such
+                // as `self.PriceTable = cached` or `self.PriceTable.Data` are
+                // skipped.
+                if let Some(open) = table_assignment_brace(bytes, idx) {
+                    marker_found = true;
+                    idx = open;
+                }
+                continue;
+            }
+        } else if c == b'{' {
+            if depth == 0 {
+                segment_start = idx + 1;
+            }
+            depth += 1;
+        } else if c == b'}' && depth > 0 {
+            depth -= 1;
+            if depth == 0 {
+                table.push_str(&text[segment_start..idx]);
+                return Ok(table.trim().to_string());
+            }
+        }
+        idx += 1;
+    }
+
+    if !marker_found || depth == 0 {
+        Err(ParseError::MarkerNotFound)
+    } else if in_string.is_some() {
+        Err(ParseError::UnterminatedString)
+    } else {
+        Err(ParseError::UnbalancedBraces)
+    }
+}
+
+/// Returns the level of a Lua long bracket (`[[` is 0, `[==[` is 2) opening
+/// at `idx`, if there is one.
+fn long_bracket_level(bytes: &[u8], idx: usize) -> Option<usize> {
+    if bytes.get(idx) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[idx + 1..].iter().take_while(|&&b| b == b'=').count();
+    (bytes.get(idx + 1 + level) == Some(&b'[')).then_some(level)
+}
+
+/// Returns the index just past the `]=*]` closing the long bracket of
+/// `level` that opens at `idx`.
+fn find_long_bracket_close(bytes: &[u8], idx: usize, level: usize) -> Option<usize> {
+    let mut close = vec![b'='; level + 2];
+    close[0] = b']';
+    close[level + 1] = b']';
+    let body = idx + level + 2;
+    bytes[body..]
+        .windows(close.len())
+        .position(|w| w == close.as_slice())
+        .map(|pos| body + pos + close.len())
+}
+
+/// Rewrites Lua long strings (`[[...]]`, `[=[...]=]`, ...) as JSON string
+/// literals, leaving quoted strings untouched.
+fn convert_long_strings(lua: &str) -> String {
+    let bytes = lua.as_bytes();
+    let mut out = String::with_capacity(lua.len());
+    let mut in_string: Option<u8> = None;
+    let mut escaped = false;
+    let mut segment_start = 0;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let c = bytes[idx];
+        if in_string.is_some() {
+            if escaped {
+                escaped = false;
+            } else if c == b'\\' {
+                escaped = true;
+            } else if Some(c) == in_string {
+                in_string = None;
+            }
+        } else if c == b'\'' || c == b'"' {
+            in_string = Some(c);
+        } else if let Some(level) = long_bracket_level(bytes, idx)
+            && let Some(end) = find_long_bracket_close(bytes, idx, level)
+        {
+            let mut content = &lua[idx + level + 2..end - level - 2];
+            // Lua drops a newline directly after the opening bracket.
+            content = content.strip_prefix("\r\n").or_else(|| content.strip_prefix('\n')).unwrap_or(content);
+            out.push_str(&lua[segment_start..idx]);
+            out.push_str(&serde_json::to_string(content).expect("strings always serialize"));
+            segment_start = end;
+            idx = end;
+            continue;
+        }
+        idx += 1;
+    }
+    out.push_str(&lua[segment_start..]);
+    out
+}
+
+pub(crate) fn extract_item_lookup_table(text: &str) -> Option<String> {
+    let re = Regex::new(r"self\.ItemLookUpTable\s*=\s*\{(?s)(?P<body>.*?)\}\s*end").unwrap();
+    if let Some(caps) = re.captures(text) {
+        return Some(caps["body"].to_string());
+    }
+    None
+}
+
+/// ESO launched on 2014-04-04; no real TTC table predates it.
+const ESO_LAUNCH_TIMESTAMP: i64 = 1_396_569_600;
+
+/// How far ahead of the local clock a table timestamp may be before it is
+/// treated as garbage.
+const MAX_FUTURE_SKEW_SECS: i64 = 24 * 60 * 60;
+
+fn extract_timestamp_from_block(block: &str) -> Option<i64> {
+    let re = Regex::new(r#"\[?\s*['\"]?TimeStamp['\"]?\s*\]?\s*=\s*(\d+)"#).unwrap();
+    if let Some(caps) = re.captures(block)
+        && let Some(m) = caps.get(1)
+        && let Ok(ts) = m.as_str().parse::<i64>()
+        && is_plausible_timestamp(ts)
+    {
+        return Some(ts);
+    }
+    None
+}
+
+fn is_plausible_timestamp(ts: i64) -> bool {
+    ts >= ESO_LAUNCH_TIMESTAMP && ts <= Utc::now().timestamp() + MAX_FUTURE_SKEW_SECS
+}
+
+fn lua_to_json(lua: &str) -> String {
+    let mut s = convert_long_strings(lua);
+    s = s.replace("\r\n", "\n");
+    s = Regex::new(r"'([^'\\]*(?:\\.[^'\\]*)*)'")
+        .unwrap()
+        .replace_all(&s, r#""$1""#)
+        .to_string();
+    s = Regex::new(r#"\[\s*"([^"]*)"\s*\]\s*="#)
+        .unwrap()
+        .replace_all(&s, r#""$1":"#)
+        .to_string();
+    s = Regex::new(r#"\[\s*'([^']*)'\s*\]\s*="#)
+        .unwrap()
+        .replace_all(&s, r#""$1":"#)
+        .to_string();
+    // Numeric keys may use exponent notation (`[1e+15]`), as Lua's `%.14g`
+    // does, and Lua allows space after a minus sign (`[- 1]`). The quoted
+    // keys above no longer start with `[`, so each key is rewritten once.
+    s = Regex::new(r#"\[\s*(-?)\s*(\d+(?:\.\d+)?(?:[eE][+-]?\d+)?)\s*\]\s*="#)
+        .unwrap()
+        .replace_all(&s, |caps: &regex::Captures| format!("\"{}\":", numeric_key(&format!("{}{}", &caps[1], &caps[2]))))
+        .to_string();
+    s = Regex::new(r#"(?P<prefix>(?:\{|,|\[|\s))(?P<key>[A-Za-z_]\w*)\s*="#)
+        .unwrap()
+        .replace_all(&s, "${prefix}\"${key}\":")
+        .to_string();
+    s = Regex::new(r"\bnil\b").unwrap().replace_all(&s, "null").to_string();
+    s = Regex::new(r"\btrue\b").unwrap().replace_all(&s, "true").to_string();
+    s = Regex::new(r"\bfalse\b").unwrap().replace_all(&s, "false").to_string();
+
+    s
+}
+
+/// Spells a numeric table key the way Lua reads it, so `[12345]`,
+/// `[012345]`, `[12345.0]` and `[1.2345e4]` all become the key `12345`, the
+/// same as the quoted `["12345"]`, and `[-0]` is `0`. Keys with a fractional
+/// part are spelled the shortest way, `[12.50]` as `12.5`; integers too large
+/// for an `i64` keep their source text.
+pub(crate) fn numeric_key(text: &str) -> String {
+    if let Ok(n) = text.parse::<i64>() {
+        return n.to_string();
+    }
+    match text.parse::<f64>() {
+        Ok(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => (f as i64).to_string(),
+        Ok(f) if f.is_finite() && f.fract() != 0.0 => f.to_string(),
+        _ => text.to_string(),
+    }
+}
+
+fn remove_trailing_commas_recursive(mut text: String) -> String {
+    let re = Regex::new(r",\s*(?P<close>[\}\]])").unwrap();
+    loop {
+        let new_text = re.replace_all(&text, "$close").to_string();
+        if new_text == text {
+            break;
+        }
+        text = new_text;
+    }
+
+    text = Regex::new(r",\s*$").unwrap().replace_all(&text, "").to_string();
+
+    text
+}
+
+/// Log target of the per-leaf trace lines from walking the `Data` table: the
+/// path of each leaf and whether it was read as a price node. Too noisy for
+/// `RUST_LOG=trace` on a full table, so it is usually enabled on its own.
+pub const TRAVERSAL_LOG_TARGET: &str = "ttcdata::traversal";
+
+/// Keys that mark a table in `Data` as a price node rather than another
+/// level of the item path. They must stay in step with the renames on
+/// [`PriceInfo`].
+pub const PRICE_NODE_KEYS: [&str; 2] = ["A", "X"];
+
+/// Every key [`PriceInfo`] reads, for spotting a table that has some of them
+/// without being recognised as a price node.
+const PRICE_INFO_KEYS: [&str; 9] = ["A", "X", "N", "EC", "AC", "S", "SA", "SE", "SAC"];
+
+/// Lazily walks the `Data` table, yielding an entry per price node. The
+/// walk consumes the converted table as it goes.
+struct EntriesIter {
+    /// Maps still being walked, innermost last.
+    stack: Vec<serde_json::map::IntoIter>,
+    /// Key of each map on `stack` below `Data`.
+    path: Vec<String>,
+    /// A setup error, yielded once before the iterator ends.
+    error: Option<ParseError>,
+    timestamp: Option<i64>,
+    stats: ParseStats,
+}
+
+impl EntriesIter {
+    fn new(lua_text: &str, inspect: impl FnOnce(&serde_json::Value)) -> Result<Self, ParseError> {
+        let extracted = extract_price_table(lua_text)?;
+        let timestamp = extract_timestamp_from_block(&extracted);
+
+        #[cfg(feature = "full-lua")]
+        let parsed = crate::lua_ast::table_to_value(&extracted)?;
+        #[cfg(not(feature = "full-lua"))]
+        let parsed = regex_table_to_value(&extracted)?;
+        inspect(&parsed);
+
+        let data = match parsed {
+            serde_json::Value::Object(mut table) => table.remove("Data"),
+            _ => None,
+        };
+        let Some(serde_json::Value::Object(data)) = data else {
+            return Err(ParseError::MissingData);
+        };
+        if data.is_empty() {
+            warn!("Data table in the price table is empty");
+        }
+        let stats = ParseStats { objects_visited: 1, ..ParseStats::default() };
+        Ok(EntriesIter { stack: vec![data.into_iter()], path: Vec::new(), error: None, timestamp, stats })
+    }
+
+    fn failed(error: ParseError) -> Self {
+        EntriesIter { stack: Vec::new(), path: Vec::new(), error: Some(error), timestamp: None, stats: ParseStats::default() }
+    }
+
+    /// The entry for a price node at `self.path`.
+    fn entry(&self, price: PriceInfo) -> ItemEntry {
+        match &self.path[..] {
+            [item_id, quality, level, trait_id, variant] => ItemEntry {
+                item_id: item_id.clone(),
+                quality: quality.clone(),
+                level: level.clone(),
+                trait_id: trait_id.clone(),
+                variant: variant.clone(),
+                depth: ITEM_PATH_DEPTH,
+                region: None,
+                price,
+            },
+            path => {
+                warn!(
+                    "Price node at depth {} (expected {}) at Data/{}; only item_id is kept",
+                    path.len(), ITEM_PATH_DEPTH, path.join("/")
+                );
+                ItemEntry {
+                    item_id: path[0].clone(),
+                    quality: String::new(),
+                    level: String::new(),
+                    trait_id: String::new(),
+                    variant: String::new(),
+                    depth: path.len(),
+                    region: None,
+                    price,
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for EntriesIter {
+    type Item = Result<ItemEntry, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+        loop {
+            let Some((key, value)) = self.stack.last_mut()?.next() else {
+                self.stack.pop();
+                self.path.pop();
+                continue;
+            };
+            match value {
+                serde_json::Value::Object(inner) if PRICE_NODE_KEYS.iter().all(|k| inner.contains_key(*k)) => {
+                    self.path.push(key);
+                    let item = match PriceInfo::deserialize(serde_json::Value::Object(inner)) {
+                        Ok(price) => {
+                            trace!(target: TRAVERSAL_LOG_TARGET, "Data/{}: price node", self.path.join("/"));
+                            self.stats.price_nodes += 1;
+                            Ok(self.entry(price))
+                        }
+                        Err(e) => {
+                            trace!(target: TRAVERSAL_LOG_TARGET, "Data/{}: unreadable price node", self.path.join("/"));
+                            self.stats.nodes_skipped += 1;
+                            Err(ParseError::InvalidPriceNode { path: self.path.join("/"), message: e.to_string() })
+                        }
+                    };
+                    self.path.pop();
+                    return Some(item);
+                }
+                serde_json::Value::Object(inner) => {
+                    if PRICE_INFO_KEYS.iter().any(|k| inner.contains_key(*k)) {
+                        // Only the first is logged, as a renamed key would
+                        // otherwise warn once per item.
+                        if self.stats.partial_price_nodes == 0 {
+                            let keys: Vec<&str> = inner.keys().map(String::as_str).collect();
+                            warn!(
+                                "Table at Data/{}/{} has keys {} but not all of {}; the price node keys may have changed",
+                                self.path.join("/"), key, keys.join(","), PRICE_NODE_KEYS.join(",")
+                            );
+                        }
+                        self.stats.partial_price_nodes += 1;
+                    }
+                    self.stats.objects_visited += 1;
+                    self.path.push(key);
+                    self.stack.push(inner.into_iter());
+                }
+                value => {
+                    trace!(target: TRAVERSAL_LOG_TARGET, "Data/{}/{}: skipped, not a table ({})", self.path.join("/"), key, value);
+                    self.stats.nodes_skipped += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Default backend: rewrites the Lua table into JSON text and parses that.
+#[cfg_attr(feature = "full-lua", allow(dead_code))]
+fn regex_table_to_value(table_body: &str) -> Result<serde_json::Value, ParseError> {
+    let jsonish = lua_to_json(table_body);
+    let cleaned = remove_trailing_commas_recursive(jsonish);
+    let wrapped = format!("{{{}}}", cleaned);
+    serde_json::from_str(&wrapped).map_err(|e| json_error(&wrapped, &e))
+}
+
+/// Bytes of generated JSON shown on each side of a syntax error.
+const JSON_SNIPPET_RADIUS: usize = 40;
+
+fn json_error(json: &str, error: &serde_json::Error) -> ParseError {
+    // serde_json reports a 1-based line and a column counted in bytes.
+    let line_start: usize = json.split_inclusive('\n').take(error.line().saturating_sub(1)).map(str::len).sum();
+    let offset = (line_start + error.column()).min(json.len());
+    let mut start = offset.saturating_sub(JSON_SNIPPET_RADIUS);
+    let mut end = (offset + JSON_SNIPPET_RADIUS).min(json.len());
+    while !json.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !json.is_char_boundary(end) {
+        end += 1;
+    }
+    // The message from serde_json already ends in its own "at line .. column ..".
+    let message = error.to_string();
+    let message = message.split(" at line ").next().unwrap_or(&message).to_string();
+    ParseError::InvalidJson {
+        message,
+        line: error.line(),
+        column: error.column(),
+        snippet: json[start..end].replace('\n', " "),
+    }
+}
+
+/// Parses a `PriceTableXX.lua` file into price entries and the table timestamp.
+pub fn parse_ttc_lua(lua_text: &str) -> Result<PriceTable, ParseError> {
+    parse_ttc_lua_with(lua_text, |_| {})
+}
+
+/// [`parse_ttc_lua`] for untrusted input: any panic left in the parser is
+/// caught and returned as [`ParseError::Internal`] instead of unwinding into
+/// the caller.
+pub fn try_parse_ttc_lua(lua_text: &str) -> Result<PriceTable, ParseError> {
+    panic::catch_unwind(|| parse_ttc_lua(lua_text)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(ParseError::Internal(message))
+    })
+}
+
+/// Like [`parse_ttc_lua`], but first hands `inspect` the JSON value the Lua
+/// table was converted into, before any entries are read from it. Useful to
+/// see what the parser saw when an entry goes missing.
+pub fn parse_ttc_lua_with(lua_text: &str, inspect: impl FnOnce(&serde_json::Value)) -> Result<PriceTable, ParseError> {
+    let mut results = Vec::new();
+    let (timestamp, stats) = walk_price_table(lua_text, inspect, &mut |entry| results.push(entry))?;
+
+    let dropped = dedup_entries(&mut results);
+    if dropped > 0 {
+        info!("Dropped {} duplicate price entries", dropped);
+    }
+    sort_entries(&mut results);
+
+    Ok(PriceTable {
+        entries: results,
+        timestamp,
+        captured_at: timestamp.and_then(|ts| DateTime::from_timestamp(ts, 0)),
+        stats,
+    })
+}
+
+/// Streaming form of [`parse_ttc_lua`]: hands every entry to `on_entry` as
+/// the `Data` table is walked, so the full list never has to exist. Entries
+/// are neither deduplicated nor sorted by identity. Returns the table
+/// timestamp and the parse coverage.
+pub fn for_each_ttc_entry(lua_text: &str, mut on_entry: impl FnMut(ItemEntry)) -> Result<(Option<i64>, ParseStats), ParseError> {
+    walk_price_table(lua_text, |_| {}, &mut on_entry)
+}
+
+/// Lazy form of [`parse_ttc_lua`]: yields each entry as the `Data` table is
+/// walked, in the same order as [`for_each_ttc_entry`]. The Lua is still
+/// converted up front; only the entries are produced on demand, and they are
+/// neither deduplicated nor sorted. A price node that fails to read is
+/// yielded as [`ParseError::InvalidPriceNode`] and the walk carries on; any
+/// other error is yielded once and ends the iterator.
+pub fn entries_iter(lua_text: &str) -> impl Iterator<Item = Result<ItemEntry, ParseError>> {
+    EntriesIter::new(lua_text, |_| {}).unwrap_or_else(EntriesIter::failed)
+}
+
+fn walk_price_table(
+    lua_text: &str,
+    inspect: impl FnOnce(&serde_json::Value),
+    on_entry: &mut dyn FnMut(ItemEntry),
+) -> Result<(Option<i64>, ParseStats), ParseError> {
+    let mut entries = EntriesIter::new(lua_text, inspect)?;
+    for entry in &mut entries {
+        match entry {
+            Ok(entry) => on_entry(entry),
+            Err(e) => warn!("Skipping {}", e),
+        }
+    }
+
+    let stats = entries.stats;
+    if stats.skipped_ratio() > SKIPPED_WARN_RATIO {
+        warn!(
+            "Skipped {} of {} nodes in the Data table; the price table layout may have changed",
+            stats.nodes_skipped, stats.nodes_skipped + stats.price_nodes
+        );
+    }
+    if stats.partial_price_nodes > 1 {
+        warn!("{} tables in the Data table had only some of the price node keys", stats.partial_price_nodes);
+    }
+    Ok((entries.timestamp, stats))
+}
+
+/// Collapses entries sharing the same identity, keeping the one backed by
+/// more listings. The first occurrence keeps its position in the output.
+/// Repeated keys inside a single Lua table never reach this point: the
+/// table parser already keeps only the last one.
+fn dedup_entries(entries: &mut Vec<ItemEntry>) -> usize {
+    let before = entries.len();
+    let mut seen: HashMap<(String, String, String, String, String), usize> = HashMap::new();
+    let mut kept: Vec<ItemEntry> = Vec::with_capacity(before);
+    for entry in entries.drain(..) {
+        let (item_id, quality, level, trait_id, variant) = entry.identity();
+        let key = (item_id.to_string(), quality.to_string(), level.to_string(), trait_id.to_string(), variant.to_string());
+        match seen.get(&key) {
+            Some(&index) => {
+                if entry.price.entry_count > kept[index].price.entry_count {
+                    kept[index] = entry;
+                }
+            }
+            None => {
+                seen.insert(key, kept.len());
+                kept.push(entry);
+            }
+        }
+    }
+    *entries = kept;
+    before - entries.len()
+}
+
+/// Orders entries by identity so the output does not depend on how the
+/// table parser happens to iterate keys. Numeric keys sort by value and
+/// before any non-numeric ones, which sort as text.
+fn sort_entries(entries: &mut [ItemEntry]) {
+    fn rank(field: &str) -> (bool, i64, &str) {
+        match field.parse::<i64>() {
+            Ok(n) => (false, n, field),
+            Err(_) => (true, 0, field),
+        }
+    }
+    entries.sort_by(|a, b| {
+        let (a, b) = (a.identity(), b.identity());
+        rank(a.0).cmp(&rank(b.0))
+            .then_with(|| rank(a.1).cmp(&rank(b.1)))
+            .then_with(|| rank(a.2).cmp(&rank(b.2)))
+            .then_with(|| rank(a.3).cmp(&rank(b.3)))
+            .then_with(|| rank(a.4).cmp(&rank(b.4)))
+    });
+}
+
+/// Parses an `ItemLookUpTable_XX.lua` file into an item id to name map.
+///
+/// When several names list the same id, the first non-blank name in the
+/// file wins and the others are logged at debug level, so the join onto
+/// `item_name` does not depend on which match came last.
+pub fn parse_item_lookup(lua_text: &str) -> BTreeMap<String, String> {
+    let mut map = BTreeMap::new();
+    if let Some(body) = extract_item_lookup_table(lua_text) {
+        // The inner table may nest one level, e.g. `{[1]={id=100}}`.
+        let re = Regex::new(r#"\[\s*\"([^\"]+)\"\s*\]\s*=\s*\{((?:[^{}]|\{[^{}]*\})*)\}"#).unwrap();
+        let key_re = Regex::new(r#"^\s*(?:\[[^\]]*\]|[A-Za-z_]\w*)\s*="#).unwrap();
+        let mut unparsed = 0;
+        let mut collisions = 0;
+        for cap in re.captures_iter(&body) {
+            let name = &cap[1];
+            let ids = lookup_ids(&cap[2], &key_re);
+            if ids.is_empty() {
+                debug!("No item id in lookup entry for {:?}: {{{}}}", name, cap[2].trim());
+                unparsed += 1;
+            }
+            for id in ids {
+                match map.get_mut(id) {
+                    None => {
+                        map.insert(id.to_string(), name.to_string());
+                    }
+                    Some(kept) if kept.trim().is_empty() => *kept = name.to_string(),
+                    Some(kept) if kept != name => {
+                        debug!("Item id {} is listed as both {:?} and {:?}; keeping {:?}", id, kept, name, kept);
+                        collisions += 1;
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        if unparsed > 0 {
+            warn!("Skipped {} item lookup entries without a readable item id", unparsed);
+        }
+        if collisions > 0 {
+            info!("Kept the first name for {} item ids listed under several names", collisions);
+        }
+    }
+    map
+}
+
+/// Every integer value in a lookup entry's inner table, whatever its key
+/// looks like: `{[1]=100,[2]=101}`, `{["a"]=100}`, `{id=100}` or `{100}`.
+/// A name can map to several ids, one per variant. `key_re` matches a
+/// leading `key =`.
+fn lookup_ids<'a>(inner: &'a str, key_re: &Regex) -> Vec<&'a str> {
+    inner
+        .split([',', ';', '{', '}'])
+        .map(|field| {
+            let value = match key_re.find(field) {
+                Some(m) => &field[m.end()..],
+                None => field,
+            };
+            value.trim()
+        })
+        .filter(|value| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()))
+        .collect()
+}
@@ -0,0 +1,58 @@
+//! Per-region progress spinner. Without the `progress` feature, or with
+//! `--quiet`, every method is a no-op.
+
+use crate::{Options, Region};
+
+#[cfg(feature = "progress")]
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+#[cfg(feature = "progress")]
+use std::sync::LazyLock;
+#[cfg(feature = "progress")]
+use std::time::Duration;
+
+/// Regions run in parallel, so their spinners share one set of terminal lines.
+#[cfg(feature = "progress")]
+static BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+pub(crate) struct Progress {
+    #[cfg(feature = "progress")]
+    bar: Option<ProgressBar>,
+}
+
+impl Progress {
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    pub(crate) fn start(region: Region, options: &Options) -> Self {
+        #[cfg(feature = "progress")]
+        {
+            // indicatif hides the bar by itself when stderr is not a terminal.
+            let bar = (!options.quiet).then(|| {
+                let bar = BARS.add(ProgressBar::new_spinner());
+                bar.set_style(ProgressStyle::with_template("{spinner} {prefix}: {msg} [{elapsed}]").unwrap());
+                bar.set_prefix(region.to_string());
+                bar.enable_steady_tick(Duration::from_millis(120));
+                bar
+            });
+            Progress { bar }
+        }
+        #[cfg(not(feature = "progress"))]
+        Progress {}
+    }
+
+    /// Shows what the region is doing now, e.g. "parsing".
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    pub(crate) fn phase(&self, message: impl Into<String>) {
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.bar {
+            bar.set_message(message.into());
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
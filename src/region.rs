@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A TTC PC megaserver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    Na,
+    Eu,
+}
+
+impl Region {
+    pub const ALL: [Region; 2] = [Region::Na, Region::Eu];
+
+    pub fn csv_prefix(self) -> &'static str {
+        match self {
+            Region::Na => "na",
+            Region::Eu => "eu",
+        }
+    }
+
+    /// The region named in a file name such as `PriceTableEU.lua`, ignoring
+    /// case. The region must be a whole word of the name, split on anything
+    /// but letters and digits, or follow `PriceTable`, so `final_prices.lua`
+    /// names neither. `None` when the name mentions neither region, or both.
+    pub fn from_file_name(path: &Path) -> Option<Region> {
+        let stem = path.file_stem()?.to_string_lossy().to_ascii_lowercase();
+        let words: Vec<&str> = stem
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .map(|word| word.strip_prefix("pricetable").unwrap_or(word))
+            .collect();
+        match Region::ALL.iter().filter(|region| words.contains(&region.csv_prefix())).collect::<Vec<_>>()[..] {
+            [region] => Some(*region),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Na => write!(f, "NA"),
+            Region::Eu => write!(f, "EU"),
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "NA" => Ok(Region::Na),
+            "EU" => Ok(Region::Eu),
+            _ => Err(format!("unknown region '{}' (expected NA or EU)", s)),
+        }
+    }
+}
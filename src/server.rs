@@ -0,0 +1,944 @@
+use crate::download::{build_client, download_zip, extract_all_from_zip, extract_lua_from_zip, zip_entry_crc, CacheValidators, DownloadStatus, DEFAULT_USER_AGENT};
+use crate::market::load_item_names;
+use crate::output::{write_entries_to_csv_manual, write_entries_to_csv_zst, write_entries_to_json, write_entries_to_ndjson, write_lookup_table, write_lookup_table_zst, write_quality_stats, write_schema, write_summary, Columns, NameTemplate, OutputFormat};
+use crate::parse::{parse_item_lookup, parse_ttc_lua_with, PriceTable};
+use crate::progress::Progress;
+use crate::temp::TempFile;
+use crate::{ItemEntry, Region};
+use chrono::Utc;
+use chrono_tz::Tz;
+use log::{debug, info, warn};
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Default download URL of the NA price table.
+pub const DEFAULT_NA_URL: &str = "https://us.tamrieltradecentre.com/download/PriceTable";
+/// Default download URL of the EU price table.
+pub const DEFAULT_EU_URL: &str = "https://eu.tamrieltradecentre.com/download/PriceTable";
+
+/// Settings shared by every region processed in a run.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// How many times a failed download is retried before giving up.
+    pub retries: u32,
+    /// Connect and read timeout for downloads.
+    pub timeout: Duration,
+    /// User-Agent header sent with downloads.
+    pub user_agent: String,
+    /// Which writers to run for the price entries.
+    pub format: OutputFormat,
+    /// Write only these CSV columns, in this order. `None` writes them all.
+    pub columns: Option<Columns>,
+    /// Language code of the item lookup table, e.g. `EN` or `DE`.
+    pub language: String,
+    /// Further languages whose lookup tables are written from the same
+    /// archive, each to its own `lookup_<lang>.csv`. Names in the price CSV
+    /// still come from `language`.
+    pub extra_languages: Vec<String>,
+    /// Root directory for the dated folders and `latest/`.
+    pub output_dir: PathBuf,
+    /// Directory for the downloaded ZIP and extracted Lua files.
+    pub temp_dir: PathBuf,
+    /// Download and parse, but skip every write under `output_dir`.
+    pub dry_run: bool,
+    /// Move the downloaded ZIP and extracted Lua into `output_dir` instead of
+    /// deleting them.
+    pub keep_lua: bool,
+    /// Only write entries whose `quality` is one of these, e.g. `"5"` for
+    /// legendary. An empty list keeps every quality.
+    pub qualities: Vec<String>,
+    /// Drop entries whose price is backed by fewer than this many listings.
+    /// 0 keeps every entry.
+    pub min_entry_count: u64,
+    /// Drop entries without recorded sales, i.e. without a `sale_avg`.
+    pub require_sale: bool,
+    /// Multiply every price (not the counts) by this, to write gold per
+    /// stack instead of per unit. 1 keeps TTC's per-unit prices.
+    pub per_stack: u32,
+    /// Fail a region whose table parses into fewer entries than this, before
+    /// anything is written. 0 accepts any count.
+    pub min_expected_entries: usize,
+    /// Write each CSV as numbered parts (`na_part0.csv`, `na_part1.csv`, ...)
+    /// of at most this many rows, each with its own header.
+    pub split: Option<NonZeroUsize>,
+    /// Gzip every CSV and JSON output, adding a `.gz` suffix to its name.
+    pub gzip: bool,
+    /// Keep only the first this many entries. Applied after parsing, so it
+    /// speeds up the writers but not the parse itself.
+    pub limit: Option<usize>,
+    /// Largest uncompressed size, in bytes, accepted for a file in the ZIP.
+    pub max_extract_size: u64,
+    /// Read this local `.zip` or `.lua` file instead of downloading. A bare
+    /// `.lua` carries no item lookup table, so item names stay empty.
+    pub input: Option<PathBuf>,
+    /// Skip writing a region whose table `TimeStamp` is older than this Unix
+    /// time. Tables without a timestamp are always written.
+    pub since: Option<i64>,
+    /// Timezone whose calendar date names the dated folder. Timestamps in
+    /// the outputs stay UTC.
+    pub timezone: Tz,
+    /// Where the NA price table ZIP is downloaded from.
+    pub na_url: String,
+    /// Where the EU price table ZIP is downloaded from.
+    pub eu_url: String,
+    /// Proxy for every download. When unset, reqwest picks one up from the
+    /// `HTTP_PROXY`/`HTTPS_PROXY` environment variables, if any.
+    pub proxy: Option<String>,
+    /// Hide the progress spinner shown with the `progress` feature.
+    pub quiet: bool,
+    /// Only write the dated folder, leaving the `latest/` copies alone. The
+    /// HTTP and lookup caches in `.cache/` are still kept up to date.
+    pub no_latest: bool,
+    /// Write the JSON value each price table was converted into to this
+    /// path, with the region added to the file name (`dump.json` becomes
+    /// `dump_na.json`). Written even on a dry run.
+    pub dump_json: Option<PathBuf>,
+    /// Path of each region's dated files under `output_dir`, before the
+    /// extension.
+    pub name_template: NameTemplate,
+    /// Name the files in the dated folder after the table timestamp, e.g.
+    /// `na_1700001234.csv.zst`, so tables published on the same day do not
+    /// overwrite each other.
+    pub timestamp_names: bool,
+    /// Drop entries failing [`PriceInfo::is_sane`](crate::PriceInfo::is_sane)
+    /// instead of only counting them.
+    pub strict: bool,
+    /// Start every CSV and TSV row with the entry's region (`na`/`eu`), as
+    /// the combined CSV does.
+    pub region_column: bool,
+    /// Pause between regions when processing both. Non-zero also stops them
+    /// from downloading at the same time.
+    pub region_delay: Duration,
+    /// Stop processing further regions once one fails. Regions already
+    /// running alongside it still finish.
+    pub fail_fast: bool,
+    /// Also write `{prefix}_quality_stats.csv`, the entry count, mean average
+    /// price and total quantity of each quality tier.
+    pub quality_stats: bool,
+    /// Also write `{prefix}.schema.json` next to the CSV or TSV, the type of
+    /// each of its columns.
+    pub schema: bool,
+    /// SQLite database to upsert the price entries into, if any.
+    #[cfg(feature = "sqlite")]
+    pub sqlite: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            retries: 3,
+            timeout: Duration::from_secs(30),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            format: OutputFormat::Csv,
+            columns: None,
+            language: "EN".to_string(),
+            extra_languages: Vec::new(),
+            output_dir: PathBuf::from("."),
+            temp_dir: env::temp_dir(),
+            dry_run: false,
+            keep_lua: false,
+            qualities: Vec::new(),
+            min_entry_count: 0,
+            require_sale: false,
+            min_expected_entries: 0,
+            per_stack: 1,
+            split: None,
+            gzip: false,
+            limit: None,
+            max_extract_size: 500 * 1024 * 1024,
+            input: None,
+            since: None,
+            timezone: Tz::UTC,
+            na_url: DEFAULT_NA_URL.to_string(),
+            eu_url: DEFAULT_EU_URL.to_string(),
+            proxy: None,
+            quiet: false,
+            no_latest: false,
+            dump_json: None,
+            name_template: NameTemplate::default(),
+            timestamp_names: false,
+            strict: false,
+            region_delay: Duration::ZERO,
+            fail_fast: true,
+            quality_stats: false,
+            schema: false,
+            region_column: false,
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
+        }
+    }
+}
+
+impl Options {
+    /// Directory holding the most recent copy of every output.
+    pub fn latest_dir(&self) -> PathBuf {
+        self.output_dir.join("latest")
+    }
+
+    /// Directory for state carried between runs, such as the HTTP
+    /// validators. It is git-ignored, unlike the published outputs.
+    pub fn cache_dir(&self) -> PathBuf {
+        self.output_dir.join(".cache")
+    }
+
+    /// Download URL of `region`'s price table ZIP.
+    pub fn url(&self, region: Region) -> &str {
+        match region {
+            Region::Na => &self.na_url,
+            Region::Eu => &self.eu_url,
+        }
+    }
+
+    /// `".gz"` when outputs are gzipped, otherwise empty; appended to every
+    /// CSV and JSON file name.
+    pub fn gz_suffix(&self) -> &'static str {
+        if self.gzip { ".gz" } else { "" }
+    }
+
+    /// Hash of every option that changes what a run writes, saved with the
+    /// HTTP validators.
+    fn output_fingerprint(&self) -> String {
+        let shaping = format!(
+            "{:?}",
+            (
+                (&self.format, &self.columns, &self.language, &self.extra_languages, &self.qualities),
+                (self.min_entry_count, self.require_sale, self.per_stack, self.split, self.limit, self.strict),
+                (self.gzip, self.no_latest, &self.name_template, self.timestamp_names, self.timezone),
+                (self.region_column, self.quality_stats, self.schema),
+            )
+        );
+        #[cfg(feature = "sqlite")]
+        let shaping = format!("{} {:?}", shaping, self.sqlite);
+        Sha256::digest(shaping.as_bytes()).iter().take(8).map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// A file written by [`process_server`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputFile {
+    /// Path relative to [`Options::output_dir`], where the manifest lives.
+    pub path: PathBuf,
+    /// Size on disk once written.
+    pub bytes: u64,
+    /// Lowercase hex SHA-256 of the file as written.
+    pub sha256: String,
+}
+
+/// What [`process_server`] did for one region.
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionOutput {
+    pub region: Region,
+    /// Nothing was rewritten: the server reported the archive unchanged, or
+    /// its table was older than [`Options::since`].
+    pub unchanged: bool,
+    /// The dated folder written to, relative to [`Options::output_dir`], e.g.
+    /// `2026/03/28`.
+    pub folder: Option<PathBuf>,
+    /// Number of entries written after filtering.
+    pub entries: usize,
+    /// The table's `TimeStamp`, when present and plausible.
+    pub timestamp: Option<i64>,
+    /// How many times the download was retried before it succeeded.
+    pub retries: u32,
+    /// What the written prices were multiplied by, see
+    /// [`Options::per_stack`]. 1 for prices per unit.
+    pub price_multiplier: u32,
+    pub files: Vec<OutputFile>,
+}
+
+impl RegionOutput {
+    pub(crate) fn unchanged(region: Region) -> Self {
+        RegionOutput { region, unchanged: true, folder: None, entries: 0, timestamp: None, retries: 0, price_multiplier: 1, files: Vec::new() }
+    }
+}
+
+/// Parsed item names saved in the cache dir, keyed by the CRC-32 of
+/// the `ItemLookUpTable_XX.lua` entry they came from, so an unchanged
+/// lookup table is not extracted and parsed again.
+#[derive(Debug, Serialize, Deserialize)]
+struct LookupCache {
+    crc: u32,
+    /// [`LOOKUP_PARSER_VERSION`] that produced `names`.
+    #[serde(default)]
+    version: u32,
+    names: BTreeMap<String, String>,
+}
+
+/// Bumped whenever `parse_item_lookup` changes what it extracts, so names
+/// cached by an older build are parsed again.
+const LOOKUP_PARSER_VERSION: u32 = 3;
+
+impl LookupCache {
+    fn load(path: &Path) -> Option<Self> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+        fs::write(path, serde_json::to_string(self)?)
+    }
+}
+
+/// Every region writes the same lookup CSV, so regions processed on
+/// separate threads take turns checking and rewriting it.
+static LOOKUP_WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Downloads, parses and writes the price table and item lookup for `region`.
+pub fn process_server(region: Region, options: &Options) -> io::Result<RegionOutput> {
+    process_region(region, options, None)
+}
+
+/// [`process_server`], optionally on an archive already downloaded along
+/// with its validators. The archive is then treated as this run's own
+/// download: removed or kept with `keep_lua`, and its validators saved once
+/// everything is written.
+pub(crate) fn process_region(region: Region, options: &Options, prefetched: Option<(TempFile, CacheValidators)>) -> io::Result<RegionOutput> {
+    let (zip_name, _) = region_files(region);
+    let url = options.url(region);
+    let progress = Progress::start(region, options);
+    let csv_prefix = region.csv_prefix();
+
+    let latest_dir = options.latest_dir();
+    let cache_path = http_cache_path(region, options);
+    // Files created by this run, removed (or kept with --keep-lua) at the end.
+    // A local --input file is never among them.
+    let mut temp_files: Vec<TempFile> = Vec::new();
+    let mut fresh_validators = None;
+    let mut retries = 0;
+
+    let (zip_path, local_lua) = match (&options.input, prefetched) {
+        (Some(input), _) if is_lua_file(input) => (None, Some(input.clone())),
+        (Some(input), _) => (Some(input.clone()), None),
+        (None, Some((zip, fresh))) => {
+            fresh_validators = Some(fresh);
+            let zip_path = zip.to_path_buf();
+            temp_files.push(zip);
+            (Some(zip_path), None)
+        }
+        (None, None) => {
+            let zip = TempFile::unique(&options.temp_dir, zip_name);
+            // A dry run always fetches the full archive; a 304 would leave nothing to parse.
+            let validators = load_validators(region, options);
+            let client = build_client(options)?;
+            progress.phase("downloading");
+            let (status, attempts) = download_zip(&client, url, &zip, options.retries, &validators)?;
+            retries = attempts;
+            match status {
+                DownloadStatus::Downloaded(fresh) => fresh_validators = Some(fresh),
+                DownloadStatus::NotModified => {
+                    info!("{} price table is unchanged since the last run; keeping existing outputs", region);
+                    return Ok(RegionOutput { retries, ..RegionOutput::unchanged(region) });
+                }
+            }
+            let zip_path = zip.to_path_buf();
+            temp_files.push(zip);
+            (Some(zip_path), None)
+        }
+    };
+
+    let mut lookup_map: BTreeMap<String, String> = BTreeMap::new();
+    let mut have_lookup = false;
+    let mut language = options.language.to_ascii_uppercase();
+
+    if let Some(zip_path) = &zip_path {
+        let (used, names) = read_lookup(region, zip_path, &options.language, options)?;
+        language = used;
+        if let Some(names) = names {
+            lookup_map = names;
+            have_lookup = true;
+        }
+    } else {
+        warn!("Reading a bare .lua file, which has no item lookup table; item names will be empty");
+    }
+
+    let gz = options.gz_suffix();
+    let lookup_name = format!("{}{}", lookup_csv_name(&language), gz);
+    let latest_lookup_path = latest_dir.join(&lookup_name);
+    let parsed = read_price_table(region, zip_path.as_deref(), local_lua, options, &mut temp_files, &progress)
+        .and_then(|table| check_entry_count(region, table, options.min_expected_entries));
+    let PriceTable { mut entries, timestamp: timestamp_opt, captured_at, stats } = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            // The names are still worth keeping when only the prices failed.
+            if have_lookup && !options.dry_run && !options.no_latest {
+                let _guard = LOOKUP_WRITE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                if lookup_has_changed(&lookup_map, &latest_lookup_path) {
+                    write_lookup_table(&lookup_map, &latest_lookup_path)?;
+                    warn!("{} price table failed; wrote its item names to {}", region, latest_lookup_path.display());
+                }
+            }
+            clean_up_downloads(options, &temp_files, Utc::now().timestamp())?;
+            return Err(e);
+        }
+    };
+    info!("Parsed {} price entries for {}.", entries.len(), region);
+    debug!("{} parse coverage: {:?}", region, stats);
+    let insane = entries.iter().filter(|e| !e.price.is_sane()).count();
+    if insane > 0 {
+        if options.strict {
+            entries.retain(|e| e.price.is_sane());
+            warn!("Dropped {} {} entries with inconsistent prices (min <= avg <= max does not hold)", insane, region);
+        } else {
+            warn!("{} {} entries have inconsistent prices (min <= avg <= max does not hold)", insane, region);
+        }
+    }
+    if !options.qualities.is_empty() {
+        entries.retain(|e| options.qualities.contains(&e.quality));
+        info!("Kept {} {} entries matching quality {}", entries.len(), region, options.qualities.join(", "));
+    }
+    if options.min_entry_count > 0 {
+        let before = entries.len();
+        entries.retain(|e| e.price.entry_count >= options.min_entry_count);
+        info!("Dropped {} {} entries seen in fewer than {} listings", before - entries.len(), region, options.min_entry_count);
+    }
+    if options.require_sale {
+        let before = entries.len();
+        entries.retain(|e| e.price.sale_avg.is_some());
+        info!("Dropped {} {} entries without recorded sales", before - entries.len(), region);
+    }
+    if let Some(limit) = options.limit.filter(|&limit| limit < entries.len()) {
+        entries.truncate(limit);
+        info!("Truncated {} entries to the first {}", region, limit);
+    }
+
+    for entry in &mut entries {
+        entry.region = Some(region);
+    }
+    if options.per_stack != 1 {
+        let factor = f64::from(options.per_stack);
+        entries.iter_mut().for_each(|e| e.price.scale_prices(factor));
+        info!("Writing {} prices per stack of {}", region, options.per_stack);
+    }
+
+    let ndt = match captured_at {
+        Some(ndt) => {
+            debug!("{} price table timestamp is {}", region, ndt);
+            ndt
+        }
+        None => {
+            warn!("No valid TimeStamp in {} price table; using the current time", region);
+            Utc::now()
+        }
+    };
+
+    let mut output = RegionOutput {
+        region,
+        unchanged: false,
+        folder: None,
+        entries: entries.len(),
+        timestamp: timestamp_opt,
+        retries,
+        price_multiplier: options.per_stack,
+        files: Vec::new(),
+    };
+
+    if let (Some(since), Some(timestamp)) = (options.since, timestamp_opt)
+        && timestamp < since
+    {
+        info!("{} price table from {} is older than --since {}; keeping existing outputs", region, ndt, since);
+        clean_up_downloads(options, &temp_files, timestamp)?;
+        output.unchanged = true;
+        return Ok(output);
+    }
+
+    if options.dry_run {
+        info!("Dry run: {} has {} entries captured at {}; nothing written", region, entries.len(), ndt);
+        clean_up_downloads(options, &temp_files, ndt.timestamp())?;
+        return Ok(output);
+    }
+
+    let dated = options.output_dir.join(options.name_template.render(region, &ndt.with_timezone(&options.timezone)));
+    let folder = dated.parent().map_or_else(|| options.output_dir.clone(), Path::to_path_buf);
+    let dated_name = dated.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| csv_prefix.to_string());
+    fs::create_dir_all(&folder)?;
+    let mut written: Vec<PathBuf> = Vec::new();
+    progress.phase(format!("writing {} entries", entries.len().to_formatted_string(&Locale::en)));
+
+    // Several tables can share a date, so --timestamp-names keeps each one
+    // in the dated folder; latest/ always uses the plain name.
+    let dated_prefix = if options.timestamp_names { format!("{}_{}", dated_name, ndt.timestamp()) } else { dated_name };
+    // TSV is the same table as the CSV with tabs between the fields.
+    let delimited = [(options.format.writes_csv(), "csv", b','), (options.format.writes_tsv(), "tsv", b'\t')];
+    for (_, extension, delimiter) in delimited.into_iter().filter(|(enabled, ..)| *enabled) {
+        let parts = csv_parts(&entries, options.split);
+        let part_count = parts.len();
+        for (part, rows) in parts.into_iter().enumerate() {
+            let dated_name = part_name(&dated_prefix, part, options.split);
+            let dated_csv = if options.gzip {
+                let path = folder.join(format!("{}.{}.gz", dated_name, extension));
+                write_entries_to_csv_manual(rows, &lookup_map, options.columns.as_ref(), delimiter, options.region_column, &path)?;
+                path
+            } else {
+                let path = folder.join(format!("{}.{}.zst", dated_name, extension));
+                write_entries_to_csv_zst(rows, &lookup_map, options.columns.as_ref(), delimiter, options.region_column, &path)?;
+                path
+            };
+            written.push(dated_csv);
+            if !options.no_latest {
+                let latest_csv = latest_dir.join(format!("{}.{}{}", part_name(csv_prefix, part, options.split), extension, gz));
+                write_entries_to_csv_manual(rows, &lookup_map, options.columns.as_ref(), delimiter, options.region_column, &latest_csv)?;
+                written.push(latest_csv);
+            }
+        }
+        // A smaller table than last time leaves higher-numbered parts in
+        // latest/ that would otherwise be read back as current.
+        if options.split.is_some() && !options.no_latest {
+            for part in part_count.. {
+                let stale = latest_dir.join(format!("{}.{}{}", part_name(csv_prefix, part, options.split), extension, gz));
+                if !stale.exists() {
+                    break;
+                }
+                fs::remove_file(&stale)?;
+                info!("Removed stale {}", stale.display());
+            }
+        }
+    }
+    let mut dirs = vec![(&folder, dated_prefix.as_str())];
+    if !options.no_latest {
+        dirs.push((&latest_dir, csv_prefix));
+    }
+    if options.format.writes_json() {
+        for (dir, prefix) in &dirs {
+            let path = dir.join(format!("{}.json{}", prefix, gz));
+            write_entries_to_json(&entries, &path)?;
+            written.push(path);
+        }
+    }
+    if options.format.writes_ndjson() {
+        for (dir, prefix) in &dirs {
+            let path = dir.join(format!("{}.ndjson{}", prefix, gz));
+            write_entries_to_ndjson(&entries, &lookup_map, &path)?;
+            written.push(path);
+        }
+    }
+    // Parquet compresses its own column chunks, so --gzip does not apply.
+    #[cfg(feature = "parquet")]
+    if options.format.writes_parquet() {
+        for (dir, prefix) in &dirs {
+            let path = dir.join(format!("{}.parquet", prefix));
+            crate::parquet_output::write_entries_to_parquet(&entries, &lookup_map, &path)?;
+            written.push(path);
+        }
+    }
+    // One schema covers the CSV and TSV, and every part of a split table.
+    if options.schema && (options.format.writes_csv() || options.format.writes_tsv()) {
+        for (dir, prefix) in &dirs {
+            let path = dir.join(format!("{}.schema.json", prefix));
+            write_schema(options.columns.as_ref(), options.region_column, &path)?;
+            written.push(path);
+        }
+    }
+    if options.quality_stats {
+        for (dir, prefix) in &dirs {
+            let path = dir.join(format!("{}_quality_stats.csv{}", prefix, gz));
+            write_quality_stats(&entries, &path)?;
+            written.push(path);
+        }
+    }
+    if !options.no_latest {
+        let summary_path = latest_dir.join(format!("{}_summary.json{}", csv_prefix, gz));
+        write_summary(&entries, timestamp_opt, options.per_stack, &summary_path)?;
+        written.push(summary_path);
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(db_path) = &options.sqlite {
+        crate::sqlite::write_entries_to_sqlite(&entries, &lookup_map, region, ndt.timestamp(), db_path)?;
+    }
+
+    // Without a lookup table there is nothing to compare, and writing the
+    // empty map would wipe the names from the previous run.
+    if have_lookup {
+        write_lookup_csvs(&lookup_map, &lookup_name, &folder, options, &mut written)?;
+    }
+    if let Some(zip_path) = &zip_path {
+        for extra in extra_languages(options) {
+            // read_lookup would fall back to the EN names.
+            if zip_entry_crc(zip_path, &lookup_lua_name(&extra))?.is_none() {
+                warn!("{} not found in ZIP archive; skipping {}", lookup_lua_name(&extra), lookup_csv_name(&extra));
+                continue;
+            }
+            let (_, names) = read_lookup(region, zip_path, &extra, options)?;
+            if let Some(names) = names {
+                write_lookup_csvs(&names, &format!("{}{}", lookup_csv_name(&extra), gz), &folder, options, &mut written)?;
+            }
+        }
+    }
+
+    clean_up_downloads(options, &temp_files, ndt.timestamp())?;
+
+    // Only remember the validators once everything was written, so a failed
+    // run is retried in full instead of being skipped as "not modified".
+    if let Some(fresh_validators) = fresh_validators {
+        CacheValidators { options: Some(options.output_fingerprint()), ..fresh_validators }.save(&cache_path)?;
+    }
+
+    let relative = |path: &Path| path.strip_prefix(&options.output_dir).unwrap_or(path).to_path_buf();
+    output.folder = Some(relative(&folder));
+    output.files = written
+        .into_iter()
+        .map(|path| {
+            let bytes = fs::metadata(&path)?.len();
+            let sha256 = sha256_file(&path)?;
+            Ok(OutputFile { path: relative(&path), bytes, sha256 })
+        })
+        .collect::<io::Result<_>>()?;
+    Ok(output)
+}
+
+/// Fetches `region`'s archive (or reads the `input` ZIP) and extracts every
+/// file in it under `dir`, without parsing anything. Returns the files
+/// written.
+pub fn extract_archive(region: Region, options: &Options, dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if options.input.as_deref().is_some_and(is_lua_file) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--input is a .lua file, not an archive"));
+    }
+    let (zip_path, _download) = fetch_archive(region, options)?;
+    extract_all_from_zip(&zip_path, dir, options.max_extract_size)
+}
+
+/// The `input` ZIP, or else `region`'s archive downloaded to `temp_dir`,
+/// along with the guard that removes the download once dropped.
+/// The download is unconditional: a 304 would leave nothing to read, and the
+/// saved validators describe the last processed price table.
+fn fetch_archive(region: Region, options: &Options) -> io::Result<(PathBuf, Option<TempFile>)> {
+    if let Some(input) = &options.input {
+        return Ok((input.clone(), None));
+    }
+    let (zip_name, _) = region_files(region);
+    let zip = TempFile::unique(&options.temp_dir, zip_name);
+    let client = build_client(options)?;
+    download_zip(&client, options.url(region), &zip, options.retries, &CacheValidators::default())?;
+    Ok((zip.to_path_buf(), Some(zip)))
+}
+
+/// The slices of `entries` written to one CSV each: all of them without
+/// `--split`, else runs of at most `split` rows. An empty table still gets
+/// one (header-only) file.
+fn csv_parts(entries: &[ItemEntry], split: Option<NonZeroUsize>) -> Vec<&[ItemEntry]> {
+    match split {
+        Some(rows) if !entries.is_empty() => entries.chunks(rows.get()).collect(),
+        _ => vec![entries],
+    }
+}
+
+/// `prefix` for an unsplit CSV, `{prefix}_part{part}` with `--split`.
+pub fn part_name(prefix: &str, part: usize, split: Option<NonZeroUsize>) -> String {
+    if split.is_some() { format!("{}_part{}", prefix, part) } else { prefix.to_string() }
+}
+
+/// Fails when a table parses into fewer than `min_expected` entries, which
+/// is what a TTC format change usually looks like rather than a parse error.
+fn check_entry_count(region: Region, table: PriceTable, min_expected: usize) -> io::Result<PriceTable> {
+    if table.entries.len() < min_expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} price table parsed into only {} entries, fewer than the {} expected; the TTC format may have changed",
+                region,
+                table.entries.len(),
+                min_expected
+            ),
+        ));
+    }
+    Ok(table)
+}
+
+/// Extracts the price table from `zip_path`, unless a bare `.lua` file was
+/// given as `local_lua`, and parses it.
+fn read_price_table(
+    region: Region,
+    zip_path: Option<&Path>,
+    local_lua: Option<PathBuf>,
+    options: &Options,
+    temp_files: &mut Vec<TempFile>,
+    progress: &Progress,
+) -> io::Result<PriceTable> {
+    let lua_output = match (zip_path, local_lua) {
+        (_, Some(local_lua)) => local_lua,
+        (Some(zip_path), None) => {
+            let (_, lua_filename) = region_files(region);
+            let lua_file = TempFile::unique(&options.temp_dir, lua_filename);
+            let lua_output = lua_file.to_path_buf();
+            temp_files.push(lua_file);
+            progress.phase("extracting");
+            extract_lua_from_zip(zip_path, lua_filename, &lua_output, options.max_extract_size)?;
+            lua_output
+        }
+        (None, None) => unreachable!("every input without a .lua file is a ZIP"),
+    };
+
+    progress.phase("parsing");
+    let lua_text = read_lua_text(&lua_output)?;
+    let mut dumped = Ok(());
+    let parsed = parse_ttc_lua_with(&lua_text, |value| {
+        if let Some(path) = &options.dump_json {
+            dumped = dump_json(value, &region_dump_path(path, region));
+        }
+    });
+    dumped?;
+    parsed.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads a Lua file as text, dropping a leading UTF-8 BOM. Invalid UTF-8,
+/// which odd item names sometimes carry, is replaced with U+FFFD instead of
+/// failing the whole region.
+fn read_lua_text(path: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(text) => Ok(text.to_string()),
+        Cow::Owned(text) => {
+            let replaced = text.matches(char::REPLACEMENT_CHARACTER).count();
+            warn!("{} is not valid UTF-8; replaced {} invalid sequences with U+FFFD", path.display(), replaced);
+            Ok(text)
+        }
+    }
+}
+
+/// Writes `names` as `lookup_name` to `folder` and `latest/` when they differ
+/// from the ones already in `latest/`, adding the files to `written`.
+fn write_lookup_csvs(names: &BTreeMap<String, String>, lookup_name: &str, folder: &Path, options: &Options, written: &mut Vec<PathBuf>) -> io::Result<()> {
+    let latest_lookup_path = options.latest_dir().join(lookup_name);
+    let _guard = LOOKUP_WRITE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if !lookup_has_changed(names, &latest_lookup_path) {
+        return Ok(());
+    }
+    let dated_lookup_path = folder.join(lookup_name);
+    if options.gzip {
+        write_lookup_table(names, &dated_lookup_path)?;
+    } else {
+        write_lookup_table_zst(names, &dated_lookup_path)?;
+    }
+    written.push(dated_lookup_path);
+    if !options.no_latest {
+        write_lookup_table(names, &latest_lookup_path)?;
+        written.push(latest_lookup_path);
+    }
+    Ok(())
+}
+
+/// `options.extra_languages` in upper case, without repeats or `language`.
+fn extra_languages(options: &Options) -> Vec<String> {
+    let mut languages: Vec<String> = Vec::new();
+    for language in &options.extra_languages {
+        let language = language.to_ascii_uppercase();
+        if !language.eq_ignore_ascii_case(&options.language) && !languages.contains(&language) {
+            languages.push(language);
+        }
+    }
+    languages
+}
+
+/// Reads `region`'s item lookup table in `zip_path` for `language`, falling
+/// back to EN names when that language is missing. The parsed names are
+/// cached in `.cache/` and reused while the entry's CRC is unchanged.
+/// Returns the language actually read, and `None` for the names when the
+/// archive has no usable lookup table.
+fn read_lookup(region: Region, zip_path: &Path, language: &str, options: &Options) -> io::Result<(String, Option<BTreeMap<String, String>>)> {
+    let mut language = language.to_ascii_uppercase();
+    let mut crc = zip_entry_crc(zip_path, &lookup_lua_name(&language))?;
+    if crc.is_none() && language != "EN" {
+        warn!("{} not found in ZIP archive; falling back to EN item names", lookup_lua_name(&language));
+        language = "EN".to_string();
+        crc = zip_entry_crc(zip_path, &lookup_lua_name(&language))?;
+    }
+    let lookup_filename = lookup_lua_name(&language);
+    let lookup_cache_path = options.cache_dir().join(format!("lookup_cache_{}.json", language.to_ascii_lowercase()));
+
+    let Some(crc) = crc else {
+        warn!("{} not found in ZIP archive; item names will be empty", lookup_filename);
+        return Ok((language, None));
+    };
+    if let Some(cache) = LookupCache::load(&lookup_cache_path).filter(|cache| cache.crc == crc && cache.version == LOOKUP_PARSER_VERSION) {
+        debug!("{} is unchanged (CRC {:08x}); reusing the cached item names", lookup_filename, crc);
+        return Ok((language, Some(cache.names)));
+    }
+
+    // Both regions read their lookup at the same time, so each extracts to
+    // its own file.
+    let lookup_output = TempFile::unique(&options.temp_dir, &format!("{}_{}", region.csv_prefix(), lookup_filename));
+    if let Err(e) = extract_lua_from_zip(zip_path, &lookup_filename, &lookup_output, options.max_extract_size) {
+        warn!("Could not extract {} ({}); item names will be empty", lookup_filename, e);
+        return Ok((language, None));
+    }
+    let lookup_text = read_lua_text(&lookup_output)?;
+    let lookup_map = parse_item_lookup(&lookup_text);
+    drop(lookup_output);
+    if !options.dry_run {
+        let _guard = LOOKUP_WRITE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        LookupCache { crc, version: LOOKUP_PARSER_VERSION, names: lookup_map.clone() }.save(&lookup_cache_path)?;
+    }
+    Ok((language, Some(lookup_map)))
+}
+
+/// Fetches `region`'s archive (or reads the `input` ZIP) and writes only its
+/// item lookup CSVs to `latest/`, one for `language` and one for each of
+/// `extra_languages` found, without touching the price table. Returns the
+/// paths of the CSVs, which a dry run leaves unwritten.
+pub fn process_lookup(region: Region, options: &Options) -> io::Result<Vec<PathBuf>> {
+    if options.input.as_deref().is_some_and(is_lua_file) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "a bare .lua file has no item lookup table"));
+    }
+    let (zip_path, download) = fetch_archive(region, options)?;
+    let (language, names) = read_lookup(region, &zip_path, &options.language, options)?;
+    let Some(names) = names else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} has no readable item lookup table", zip_path.display())));
+    };
+    let mut tables = vec![(language, names)];
+    for extra in extra_languages(options) {
+        if zip_entry_crc(&zip_path, &lookup_lua_name(&extra))?.is_none() {
+            warn!("{} not found in ZIP archive; skipping {}", lookup_lua_name(&extra), lookup_csv_name(&extra));
+            continue;
+        }
+        if let (_, Some(names)) = read_lookup(region, &zip_path, &extra, options)? {
+            tables.push((extra, names));
+        }
+    }
+    drop(download);
+
+    let mut paths = Vec::new();
+    for (language, names) in tables {
+        let path = options.latest_dir().join(format!("{}{}", lookup_csv_name(&language), options.gz_suffix()));
+        if options.dry_run {
+            info!("Dry run: read {} {} item names; nothing written", names.len(), language);
+        } else {
+            let _guard = LOOKUP_WRITE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            write_lookup_table(&names, &path)?;
+            info!("Wrote {} item names to {}", names.len(), path.display());
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// ZIP name and price table file name for `region`.
+pub(crate) fn region_files(region: Region) -> (&'static str, &'static str) {
+    match region {
+        Region::Na => ("PriceTableNA.zip", "PriceTableNA.lua"),
+        Region::Eu => ("PriceTableEU.zip", "PriceTableEU.lua"),
+    }
+}
+
+/// Sidecar holding the HTTP validators of the last processed download.
+pub(crate) fn http_cache_path(region: Region, options: &Options) -> PathBuf {
+    options.cache_dir().join(format!("{}_http_cache.json", region.csv_prefix()))
+}
+
+/// The validators saved for `region`, or none for a dry run, which needs the
+/// full archive, and for validators saved under other output options: a 304
+/// would keep outputs shaped by those, e.g. trimmed by `--limit`.
+pub(crate) fn load_validators(region: Region, options: &Options) -> CacheValidators {
+    if options.dry_run {
+        return CacheValidators::default();
+    }
+    let validators = CacheValidators::load(&http_cache_path(region, options));
+    if validators.options.as_deref() != Some(options.output_fingerprint().as_str()) {
+        if validators != CacheValidators::default() {
+            info!("{} outputs were last written with other options; downloading the full archive", region);
+        }
+        return CacheValidators::default();
+    }
+    validators
+}
+
+/// `dump.json` becomes `dump_na.json`, so both regions can share one flag.
+fn region_dump_path(path: &Path, region: Region) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{}_{}.{}", stem, region.csv_prefix(), extension.to_string_lossy())),
+        None => path.with_file_name(format!("{}_{}", stem, region.csv_prefix())),
+    }
+}
+
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn dump_json(value: &serde_json::Value, path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writer.flush()?;
+    info!("Dumped the parsed price table to {}", path.display());
+    Ok(())
+}
+
+/// Deletes the temporary download files, or with `keep_lua` moves them into
+/// `output_dir` as e.g. `PriceTableNA_1700000000.lua` for later inspection.
+fn clean_up_downloads(options: &Options, paths: &[TempFile], timestamp: i64) -> io::Result<()> {
+    for path in paths {
+        if !path.exists() {
+            continue;
+        }
+        if options.keep_lua {
+            let name = Path::new(path.name());
+            let stem = name.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+            let extension = name.extension().map(|s| s.to_string_lossy()).unwrap_or_default();
+            let kept = options.output_dir.join(format!("{}_{}.{}", stem, timestamp, extension));
+            fs::create_dir_all(&options.output_dir)?;
+            // The temp dir may be on another filesystem, so rename can fail.
+            if fs::rename(path, &kept).is_err() {
+                fs::copy(path, &kept)?;
+                fs::remove_file(path)?;
+            }
+            info!("Kept {}", kept.display());
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_lua_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("lua"))
+}
+
+fn lookup_lua_name(language: &str) -> String {
+    format!("ItemLookUpTable_{}.lua", language.to_ascii_uppercase())
+}
+
+/// File name of the lookup CSV for `language`. English keeps the original
+/// `lookup.csv` name; other languages get a suffix such as `lookup_de.csv`.
+pub fn lookup_csv_name(language: &str) -> String {
+    if language.eq_ignore_ascii_case("EN") {
+        "lookup.csv".to_string()
+    } else {
+        format!("lookup_{}.csv", language.to_ascii_lowercase())
+    }
+}
+
+fn lookup_has_changed(new_map: &BTreeMap<String, String>, existing_path: &Path) -> bool {
+    let Ok(existing) = load_item_names(existing_path) else {
+        return true;
+    };
+    if existing.len() != new_map.len() {
+        return true;
+    }
+    for (id, name) in new_map {
+        match existing.get(&id.parse::<u32>().unwrap_or(0)) {
+            Some(existing_name) if existing_name == name => {}
+            _ => return true,
+        }
+    }
+    false
+}
@@ -0,0 +1,103 @@
+//! Optional SQLite output (`sqlite` feature) for keeping many days of price
+//! history in one queryable file.
+
+use crate::{ItemEntry, Region};
+use rusqlite::{params, Connection};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS prices (
+    region TEXT NOT NULL,
+    captured_at INTEGER NOT NULL,
+    item_id TEXT NOT NULL,
+    quality TEXT NOT NULL,
+    level TEXT NOT NULL,
+    trait TEXT NOT NULL,
+    variant TEXT NOT NULL,
+    avg REAL NOT NULL,
+    max REAL NOT NULL,
+    min REAL NOT NULL,
+    entry_count INTEGER NOT NULL,
+    amount_count INTEGER NOT NULL,
+    suggested_price REAL,
+    sale_avg REAL,
+    sale_entry_count INTEGER,
+    sale_amount_count INTEGER,
+    item_name TEXT,
+    PRIMARY KEY (region, captured_at, item_id, quality, level, trait, variant)
+)";
+
+const UPSERT: &str = "
+INSERT INTO prices (
+    region, captured_at, item_id, quality, level, trait, variant,
+    avg, max, min, entry_count, amount_count,
+    suggested_price, sale_avg, sale_entry_count, sale_amount_count, item_name
+) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)
+ON CONFLICT (region, captured_at, item_id, quality, level, trait, variant) DO UPDATE SET
+    avg = excluded.avg,
+    max = excluded.max,
+    min = excluded.min,
+    entry_count = excluded.entry_count,
+    amount_count = excluded.amount_count,
+    suggested_price = excluded.suggested_price,
+    sale_avg = excluded.sale_avg,
+    sale_entry_count = excluded.sale_entry_count,
+    sale_amount_count = excluded.sale_amount_count,
+    item_name = excluded.item_name";
+
+/// Upserts `entries` into the `prices` table of the database at `path`,
+/// creating the schema if needed. All rows go in one transaction.
+pub(crate) fn write_entries_to_sqlite(
+    entries: &[ItemEntry],
+    lookup_map: &BTreeMap<String, String>,
+    region: Region,
+    captured_at: i64,
+    path: &Path,
+) -> io::Result<()> {
+    write(entries, lookup_map, region, captured_at, path).map_err(io::Error::other)
+}
+
+fn write(
+    entries: &[ItemEntry],
+    lookup_map: &BTreeMap<String, String>,
+    region: Region,
+    captured_at: i64,
+    path: &Path,
+) -> rusqlite::Result<()> {
+    let mut conn = Connection::open(path)?;
+    // Regions may be written from parallel threads; wait for the other
+    // writer instead of failing with SQLITE_BUSY.
+    conn.busy_timeout(Duration::from_secs(60))?;
+    conn.execute_batch(SCHEMA)?;
+
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare(UPSERT)?;
+        let region = region.csv_prefix();
+        for e in entries {
+            stmt.execute(params![
+                region,
+                captured_at,
+                e.item_id,
+                e.quality,
+                e.level,
+                e.trait_id,
+                e.variant,
+                e.price.avg,
+                e.price.max,
+                e.price.min,
+                e.price.entry_count,
+                e.price.amount_count,
+                e.price.suggested_price,
+                e.price.sale_avg,
+                e.price.sale_entry_count,
+                e.price.sale_amount_count,
+                lookup_map.get(&e.item_id),
+            ])?;
+        }
+    }
+    tx.commit()
+}
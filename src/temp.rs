@@ -0,0 +1,84 @@
+//! Temporary files of the runs in flight. Each is removed when its guard is
+//! dropped, and [`remove_temp_files`] removes them all from a Ctrl-C handler,
+//! which no destructor would run for.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+static IN_FLIGHT: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// A file this run creates and must not leave behind. Register it before the
+/// file is created, so an interrupt in between still finds it.
+#[derive(Debug)]
+pub(crate) struct TempFile {
+    path: PathBuf,
+    /// The name the file stands for, without the process id.
+    name: String,
+}
+
+impl TempFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        in_flight().push(path.clone());
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        TempFile { path, name }
+    }
+
+    /// `name` in `dir` with the process id before the extension, e.g.
+    /// `PriceTableNA.4242.zip`, so runs sharing a temp dir each get their own
+    /// file.
+    pub(crate) fn unique(dir: &Path, name: &str) -> Self {
+        let unique = match name.rsplit_once('.') {
+            Some((stem, extension)) => format!("{}.{}.{}", stem, process::id(), extension),
+            None => format!("{}.{}", name, process::id()),
+        };
+        let mut file = TempFile::new(dir.join(unique));
+        file.name = name.to_string();
+        file
+    }
+
+    /// The file name this stands for, e.g. `PriceTableNA.zip`.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    /// Removes the file unless it was already moved away or deleted.
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let mut in_flight = in_flight();
+        if let Some(index) = in_flight.iter().position(|path| *path == self.path) {
+            in_flight.swap_remove(index);
+        }
+    }
+}
+
+fn in_flight() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    IN_FLIGHT.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Removes every temporary file still in flight: downloaded ZIPs, extracted
+/// Lua files and half-written outputs. Meant for an interrupt handler, just
+/// before the process exits.
+pub fn remove_temp_files() {
+    for path in in_flight().drain(..) {
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,106 @@
+--Tamriel Trade Centre price table, trimmed for the tests
+--Item IDs and prices are real-world shaped, not current
+
+function TamrielTradeCentre:LoadPriceTable()
+self.PriceTable=
+{
+	["Version"]=12,
+	["TimeStamp"]=1774656000,
+	["Data"]=
+	{
+		[54173]=
+		{
+			[4]=
+			{
+				[1]=
+				{
+					[-1]=
+					{
+						["0"]={["A"]=11243.58,["X"]=14000,["N"]=9500,["EC"]=184,["AC"]=412,["S"]=11000,["SA"]=10875.5,["SE"]=61,["SAC"]=133,},
+					},
+				},
+			},
+		},
+		[64690]=
+		{
+			[5]=
+			{
+				[1]=
+				{
+					[-1]=
+					{
+						["0"]={["A"]=21360.1,["X"]=27500,["N"]=17990,["EC"]=95,["AC"]=108,["S"]=20500,},
+					},
+				},
+			},
+		},
+		[64489]=
+		{
+			[1]=
+			{
+				[1]=
+				{
+					[-1]=
+					{
+						["0"]={["A"]=5.0245,["X"]=9,["N"]=2,["EC"]=1210,["AC"]=96510,["S"]=4.8,["SA"]=4.75,["SE"]=802,["SAC"]=61020,},
+					},
+				},
+			},
+		},
+		[139409]=
+		{
+			[5]=
+			{
+				[66]=
+				{
+					[11]=
+					{
+						["6|21"]={["A"]=85000,["X"]=120000,["N"]=64999,["EC"]=7,["AC"]=7,},
+						["2|6"]={["A"]=42500,["X"]=42500,["N"]=42500,["EC"]=1,["AC"]=1,},
+					},
+					[17]=
+					{
+						["6|21"]={["A"]=31000.25,["X"]=39000,["N"]=25000,["EC"]=4,["AC"]=4,["S"]=30000,},
+					},
+				},
+			},
+			[4]=
+			{
+				[66]=
+				{
+					[11]=
+					{
+						["6|21"]={["A"]=12500,["X"]=15000,["N"]=9999,["EC"]=12,["AC"]=12,["SA"]=11800,["SE"]=3,["SAC"]=3,},
+					},
+				},
+			},
+		},
+		[147300]=
+		{
+			[1]=
+			{
+				[1]=
+				{
+					[-1]=
+					{
+						["0"]={["A"]=0.5,["X"]=1,["N"]=0.1,["EC"]=22,["AC"]=4400,},
+					},
+				},
+			},
+		},
+		[166046]=
+		{
+			[3]=
+			{
+				[50]=
+				{
+					[-1]=
+					{
+						["0"]={["A"]=2999,["X"]=2999,["N"]=2999,["EC"]=1,["AC"]=1,},
+					},
+				},
+			},
+		},
+	},
+}
+end
@@ -0,0 +1,444 @@
+use std::collections::BTreeMap;
+use chrono::TimeZone;
+use std::fs;
+use ttcdata::{entries_iter, for_each_ttc_entry, format_price, parse_item_lookup, parse_ttc_lua, parse_ttc_lua_with, read_entries_from_csv, try_parse_ttc_lua, write_entries_to_combined_csv, write_ttc_lua_to_csv, Columns, NameTemplate, ParseError, Region, ITEM_PATH_DEPTH};
+
+fn price_table(data: &str) -> String {
+    format!(
+        "function TamrielTradeCentre:LoadPriceTable()\nself.PriceTable=\n{{\n[\"TimeStamp\"]=1774656000,\n[\"Data\"]=\n{{\n{}}},\n}}\nend\n",
+        data
+    )
+}
+
+fn price_node(avg: f64) -> String {
+    format!("{{[\"A\"]={},[\"X\"]=10,[\"N\"]=1,[\"EC\"]=2,[\"AC\"]=3,}}", avg)
+}
+
+#[test]
+fn parses_large_synthetic_table() {
+    let mut data = String::new();
+    for id in 0..20_000 {
+        data.push_str(&format!("[{}]={{[1]={{[50]={{[-1]={{[\"0\"]={},}},}},}},}},\n", id, price_node(id as f64 + 0.5)));
+    }
+
+    let table = parse_ttc_lua(&price_table(&data)).unwrap();
+    assert_eq!(table.len(), 20_000);
+    assert_eq!(table.timestamp, Some(1774656000));
+    assert_eq!(table.captured_at.unwrap().timestamp(), 1774656000);
+    let entries = table.entries;
+
+    let entry = entries.iter().find(|e| e.item_id == "1234").unwrap();
+    assert_eq!(entry.quality, "1");
+    assert_eq!(entry.level, "50");
+    assert_eq!(entry.trait_id, "-1");
+    assert_eq!(entry.variant, "0");
+    assert_eq!(entry.price.avg, 1234.5);
+}
+
+#[test]
+fn handles_non_ascii_text_before_table() {
+    let lua = format!("local author = \"Skjöldr Ælfwine\"\n{}", price_table(&format!("[1]={{[0]={{[3]={{[-1]={{[\"2|6\"]={},}},}},}},}},", price_node(3.0))));
+    let entries = parse_ttc_lua(&lua).unwrap().entries;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].variant, "2|6");
+}
+
+#[test]
+fn reports_unbalanced_braces() {
+    let lua = "self.PriceTable={[\"Data\"]={[1]={}";
+    assert_eq!(parse_ttc_lua(lua).unwrap_err(), ParseError::UnbalancedBraces);
+}
+
+#[test]
+fn keeps_double_dash_inside_string_literals() {
+    let data = format!(
+        "[1]={{[0]={{[3]={{[-1]={{\n[\"Axe --Special\"]={}, -- a real comment\n}},}},}},}},",
+        price_node(3.0)
+    );
+    let entries = parse_ttc_lua(&price_table(&data)).unwrap().entries;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].variant, "Axe --Special");
+}
+
+#[test]
+fn handles_long_bracket_strings_and_comments() {
+    let node = "{[\"A\"]=5,[\"X\"]=6,[\"N\"]=4,[\"EC\"]=1,[\"AC\"]=1,[\"Note\"]=[==[\nclosing } and ]] inside]==],}";
+    let data = format!(
+        "--[[ block comment with {{ braces\nspanning lines ]]\n[1]={{[0]={{[3]={{[-1]={{[ [[long key]] ]={},}},}},}},}},",
+        node
+    );
+    let entries = parse_ttc_lua(&price_table(&data)).unwrap().entries;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].variant, "long key");
+    assert_eq!(entries[0].price.avg, 5.0);
+}
+
+#[test]
+fn flags_price_nodes_at_unexpected_depth() {
+    let data = format!("[1]={{[0]={{[3]={{[-1]={{[\"0\"]={},}},}},[\"bad\"]={},}},}},", price_node(1.0), price_node(2.0));
+    let entries = parse_ttc_lua(&price_table(&data)).unwrap().entries;
+    assert_eq!(entries.len(), 2);
+
+    let good = entries.iter().find(|e| e.depth == ITEM_PATH_DEPTH).unwrap();
+    assert_eq!(good.variant, "0");
+
+    let bad = entries.iter().find(|e| e.depth == 3).unwrap();
+    assert_eq!(bad.item_id, "1");
+    assert_eq!(bad.quality, "");
+    assert_eq!(bad.variant, "");
+}
+
+#[test]
+fn drops_duplicate_entries() {
+    let low = "{[\"A\"]=1,[\"X\"]=1,[\"N\"]=1,[\"EC\"]=1,[\"AC\"]=1,}";
+    let high = "{[\"A\"]=2,[\"X\"]=2,[\"N\"]=2,[\"EC\"]=9,[\"AC\"]=9,}";
+    // Both misplaced nodes collapse to the same identity (item 7, empty fields).
+    let data = format!("[7]={{[\"a\"]={},[\"b\"]={},[0]={{[3]={{[-1]={{[\"0\"]={},}},}},}},}},", high, low, low);
+    let entries = parse_ttc_lua(&price_table(&data)).unwrap().entries;
+    assert_eq!(entries.len(), 2);
+
+    let misplaced: Vec<_> = entries.iter().filter(|e| e.depth != ITEM_PATH_DEPTH).collect();
+    assert_eq!(misplaced.len(), 1);
+    assert_eq!(misplaced[0].price.entry_count, 9);
+}
+
+#[test]
+fn parses_numbers_in_exponent_notation() {
+    let node = "{[\"A\"]=1.2e5,[\"X\"]=1.5E+06,[\"N\"]=1e2,[\"EC\"]=1,[\"AC\"]=1,}";
+    let data = format!("[1]={{[0]={{[3]={{[-1]={{[1e+15]={},}},}},}},}},", node);
+    let entries = parse_ttc_lua(&price_table(&data)).unwrap().entries;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].price.avg, 120000.0);
+    assert_eq!(entries[0].price.max, 1_500_000.0);
+    assert_eq!(entries[0].price.min, 100.0);
+    // The same key as `[1000000000000000]` to Lua, so it is spelled that way.
+    assert_eq!(entries[0].variant, "1000000000000000");
+}
+
+#[test]
+fn reports_missing_data_table() {
+    let lua = "self.PriceTable={[\"TimeStamp\"]=1774656000,[\"Prices\"]={},}";
+    assert_eq!(parse_ttc_lua(lua).unwrap_err(), ParseError::MissingData);
+
+    let table = parse_ttc_lua(&price_table("")).unwrap();
+    assert!(table.is_empty());
+}
+
+#[test]
+fn reads_the_region_back_from_a_combined_csv() {
+    let lua = price_table(&format!("[1]={{[0]={{[3]={{[-1]={{[\"0\"]={},}},}},}},}},", price_node(2.0)));
+    let entries = parse_ttc_lua(&lua).unwrap().entries;
+    assert_eq!(entries[0].region, None);
+
+    let path = std::env::temp_dir().join(format!("ttcdata_region_test_{}.csv", std::process::id()));
+    write_entries_to_combined_csv(&[(Region::Eu, entries)], &BTreeMap::new(), &path).unwrap();
+    let read = read_entries_from_csv(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(read[0].region, Some(Region::Eu));
+    assert_eq!(read[0].price.avg, 2.0);
+}
+
+#[test]
+fn round_trips_entries_through_csv() {
+    let node = "{[\"A\"]=7.4,[\"X\"]=8.5,[\"N\"]=3,[\"EC\"]=4,[\"AC\"]=10,[\"S\"]=7.1234,[\"SA\"]=6.5,[\"SE\"]=2,[\"SAC\"]=5,}";
+    let data = format!(
+        "[1]={{[0]={{[3]={{[-1]={{[\"2|6\"]={},[\"6|21\"]={},}},}},}},}},",
+        price_node(3.0),
+        node
+    );
+    let mut entries = parse_ttc_lua(&price_table(&data)).unwrap().entries;
+    for entry in &mut entries {
+        entry.region = Some(Region::Na);
+    }
+
+    let path = std::env::temp_dir().join(format!("ttcdata_round_trip_{}.csv", std::process::id()));
+    write_entries_to_combined_csv(&[(Region::Na, entries.clone())], &BTreeMap::new(), &path).unwrap();
+    let read = read_entries_from_csv(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(read, entries);
+    assert_eq!(read[1].price.sale_amount_count, Some(5));
+    assert_eq!(read[0].price.suggested_price, None);
+}
+
+#[test]
+fn sorts_entries_into_a_stable_order() {
+    let node = price_node(1.0);
+    let forward = format!("[2]={{[1]={{[1]={{[-1]={{[\"0\"]={n},}},}},}},}},[10]={{[1]={{[1]={{[-1]={{[\"0\"]={n},[\"a\"]={n},}},}},}},}},", n = node);
+    let reversed = format!("[10]={{[1]={{[1]={{[-1]={{[\"a\"]={n},[\"0\"]={n},}},}},}},}},[2]={{[1]={{[1]={{[-1]={{[\"0\"]={n},}},}},}},}},", n = node);
+
+    let write = |lua: &str, name: &str| {
+        let entries = parse_ttc_lua(lua).unwrap().entries;
+        let path = std::env::temp_dir().join(format!("ttcdata_sort_test_{}_{}.csv", std::process::id(), name));
+        write_entries_to_combined_csv(&[(Region::Na, entries)], &BTreeMap::new(), &path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        bytes
+    };
+    let first = write(&price_table(&forward), "first");
+    assert_eq!(first, write(&price_table(&forward), "second"));
+    assert_eq!(first, write(&price_table(&reversed), "reversed"));
+
+    let entries = parse_ttc_lua(&price_table(&reversed)).unwrap().entries;
+    let ids: Vec<_> = entries.iter().map(|e| (e.item_id.as_str(), e.variant.as_str())).collect();
+    assert_eq!(ids, [("2", "0"), ("10", "0"), ("10", "a")]);
+}
+
+#[test]
+fn counts_skipped_nodes() {
+    let data = format!("[1]={{[0]={{[3]={{[-1]={{[\"0\"]={},[\"1\"]=42,}},}},}},}},", price_node(1.0));
+    let stats = parse_ttc_lua(&price_table(&data)).unwrap().stats;
+    assert_eq!(stats.price_nodes, 1);
+    assert_eq!(stats.nodes_skipped, 1);
+    assert_eq!(stats.objects_visited, 5);
+    assert_eq!(stats.skipped_ratio(), 0.5);
+}
+
+#[test]
+fn maps_every_id_of_a_lookup_name() {
+    let lua = "function TamrielTradeCentre:LoadItemLookUpTable()\nself.ItemLookUpTable=\n{\n[\"Sword\"]={[1]=100,[2]=101},\n[\"Rock\"]={[1]=7,},\n}\nend\n";
+    let map = parse_item_lookup(lua);
+    assert_eq!(map.len(), 3);
+    assert_eq!(map["100"], "Sword");
+    assert_eq!(map["101"], "Sword");
+    assert_eq!(map["7"], "Rock");
+}
+
+#[test]
+fn reads_lookup_ids_under_any_key_form() {
+    let lua = "self.ItemLookUpTable=\n{\n[\"Axe\"]={[\"a\"]=5},\n[\"Bow\"]={id=6,level=50.5},\n[\"Cap\"]={[1]={id=7}},\n[\"Dirk\"]={8},\n[\"Odd\"]={[1]=\"x\"},\n}\nend\n";
+    let map = parse_item_lookup(lua);
+    assert_eq!(map.len(), 4);
+    assert_eq!(map["5"], "Axe");
+    assert_eq!(map["6"], "Bow");
+    assert_eq!(map["7"], "Cap");
+    assert_eq!(map["8"], "Dirk");
+}
+
+#[test]
+fn keeps_the_first_name_for_a_shared_lookup_id() {
+    let lua = "self.ItemLookUpTable=\n{\n[\" \"]={9},\n[\"Sword\"]={[1]=9,[2]=10},\n[\"Sword (fallback)\"]={9},\n}\nend\n";
+    let map = parse_item_lookup(lua);
+    assert_eq!(map["9"], "Sword");
+    assert_eq!(map["10"], "Sword");
+}
+
+#[test]
+fn inspects_the_intermediate_json() {
+    let lua = price_table(&format!("[1]={{[0]={{[3]={{[-1]={{[\"0\"]={},}},}},}},}},", price_node(2.0)));
+    let mut seen = None;
+    parse_ttc_lua_with(&lua, |value| seen = Some(value.clone())).unwrap();
+    let seen = seen.unwrap();
+    assert_eq!(seen["TimeStamp"], 1774656000);
+    assert_eq!(seen["Data"]["1"]["0"]["3"]["-1"]["0"]["A"], 2.0);
+
+    let mut inspected = false;
+    let err = parse_ttc_lua_with("self.PriceTable={[\"TimeStamp\"]=1,}", |_| inspected = true).unwrap_err();
+    assert_eq!(err, ParseError::MissingData);
+    assert!(inspected);
+}
+
+#[test]
+fn flags_inconsistent_prices() {
+    let data = "[1]={[0]={[3]={[-1]={[\"0\"]={[\"A\"]=5,[\"X\"]=10,[\"N\"]=1,[\"EC\"]=1,[\"AC\"]=1,},[\"1\"]={[\"A\"]=5,[\"X\"]=1,[\"N\"]=10,[\"EC\"]=1,[\"AC\"]=1,},[\"2\"]={[\"A\"]=20,[\"X\"]=10,[\"N\"]=1,[\"EC\"]=1,[\"AC\"]=1,},},},},},";
+    let entries = parse_ttc_lua(&price_table(data)).unwrap().entries;
+    let sane: Vec<&str> = entries.iter().filter(|e| e.price.is_sane()).map(|e| e.variant.as_str()).collect();
+    assert_eq!(sane, ["0"]);
+}
+
+#[test]
+fn streams_entries_without_collecting() {
+    let data = format!(
+        "[2]={{[0]={{[3]={{[-1]={{[\"0\"]={},}},}},}},}},\n[1]={{[0]={{[3]={{[-1]={{[\"0\"]={},}},}},}},}},",
+        price_node(2.0),
+        price_node(1.0)
+    );
+    let lua = price_table(&data);
+    let mut ids = Vec::new();
+    let (timestamp, stats) = for_each_ttc_entry(&lua, |entry| ids.push(entry.item_id)).unwrap();
+    ids.sort();
+    assert_eq!(ids, ["1", "2"]);
+    assert_eq!(timestamp, Some(1774656000));
+    assert_eq!(stats.price_nodes, 2);
+
+    let lookup = BTreeMap::from([("1".to_string(), "Rock".to_string())]);
+    let mut csv = Vec::new();
+    write_ttc_lua_to_csv(&lua, &lookup, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows.len(), 3);
+    assert!(rows.remove(0).starts_with("item_id,quality,level,trait,variant,avg"));
+    rows.sort();
+    assert!(rows[0].starts_with("1,0,3,-1,0,1,") && rows[0].contains(",Rock,"));
+    assert!(rows[1].starts_with("2,0,3,-1,0,2,"));
+}
+
+#[test]
+fn iterates_entries_and_reports_bad_nodes() {
+    let data = format!(
+        "[1]={{[0]={{[3]={{[-1]={{[\"0\"]={},[\"1\"]={{[\"A\"]=1,[\"X\"]=2,}},}},}},}},}},",
+        price_node(4.0)
+    );
+    let items: Vec<_> = entries_iter(&price_table(&data)).collect();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref().unwrap().price.avg, 4.0);
+    match &items[1] {
+        Err(ParseError::InvalidPriceNode { path, .. }) => assert_eq!(path, "1/0/3/-1/1"),
+        other => panic!("{:?}", other),
+    }
+
+    let items: Vec<_> = entries_iter("local x = 1").collect();
+    assert!(matches!(items[..], [Err(ParseError::MarkerNotFound)]), "{:?}", items);
+}
+
+#[test]
+fn try_parse_reads_a_minimal_table() {
+    let table = try_parse_ttc_lua(&format!("self.PriceTable={{[\"Data\"]={{[7]={{[1]={{[1]={{[0]={{[\"0\"]={},}},}},}},}},}},}}", price_node(4.0))).unwrap();
+    assert_eq!(table.len(), 1);
+    assert_eq!(table.entries[0].item_id, "7");
+    assert_eq!(table.timestamp, None);
+
+    let table = try_parse_ttc_lua(&price_table("")).unwrap();
+    assert_eq!(table.timestamp, Some(1774656000));
+}
+
+#[test]
+fn try_parse_returns_structured_errors() {
+    assert_eq!(try_parse_ttc_lua("local x = 1").unwrap_err(), ParseError::MarkerNotFound);
+    assert_eq!(try_parse_ttc_lua("self.PriceTable={[\"TimeStamp\"]=1,}").unwrap_err(), ParseError::MissingData);
+    assert_eq!(try_parse_ttc_lua("self.PriceTable={[\"Data\"]={[1]={}").unwrap_err(), ParseError::UnbalancedBraces);
+    let err = try_parse_ttc_lua("self.PriceTable={[\"Data\"]={[1]=1 2,},}").unwrap_err();
+    match err {
+        ParseError::InvalidJson { line, column, snippet, .. } => {
+            assert_eq!(line, 1);
+            assert!(column > 0);
+            assert!(snippet.contains("1 2"), "{:?}", snippet);
+        }
+        ParseError::InvalidLua(_) => {}
+        err => panic!("{:?}", err),
+    }
+}
+
+#[test]
+fn quoted_and_unquoted_integer_keys_agree() {
+    let keys = ["[12345]", "[\"12345\"]", "[012345]", "[12345.0]", "[1.2345e4]"];
+    for key in keys {
+        let data = format!("{}={{[0]={{[3]={{[-1]={{[\"0\"]={},}},}},}},}},", key, price_node(1.0));
+        let entries = parse_ttc_lua(&price_table(&data)).unwrap().entries;
+        assert_eq!(entries[0].item_id, "12345", "key {}", key);
+    }
+}
+
+#[test]
+fn rewrites_signed_and_decimal_keys_once() {
+    let cases = [("[-1]", "-1"), ("[ - 1 ]", "-1"), ("[0]", "0"), ("[-0]", "0"), ("[12.5]", "12.5"), ("[12.50]", "12.5"), ("[-0.25]", "-0.25")];
+    for (key, expected) in cases {
+        let data = format!("[1]={{[0]={{[3]={{{}={{[\"0\"]={},}},}},}},}},", key, price_node(1.0));
+        let mut json = None;
+        let table = parse_ttc_lua_with(&price_table(&data), |value| json = Some(value.clone())).unwrap();
+        assert_eq!(table.entries.len(), 1, "key {}", key);
+        assert_eq!(table.entries[0].trait_id, expected, "key {}", key);
+        let level = &json.unwrap()["Data"]["1"]["0"]["3"];
+        assert_eq!(level.as_object().unwrap().keys().collect::<Vec<_>>(), [expected], "key {}", key);
+    }
+}
+
+#[test]
+fn parses_column_lists() {
+    assert!("item_id, avg,suggested_price".parse::<Columns>().is_ok());
+    assert!("item_id,price".parse::<Columns>().unwrap_err().contains("unknown column 'price'"));
+    assert!(" , ".parse::<Columns>().is_err());
+}
+
+#[test]
+fn infers_the_region_from_file_names() {
+    use std::path::Path;
+    assert_eq!(Region::from_file_name(Path::new("dl/PriceTableEU.lua")), Some(Region::Eu));
+    assert_eq!(Region::from_file_name(Path::new("pricetablena.zip")), Some(Region::Na));
+    assert_eq!(Region::from_file_name(Path::new("PriceTable.zip")), None);
+    assert_eq!(Region::from_file_name(Path::new("na_vs_eu.lua")), None);
+    assert_eq!(Region::from_file_name(Path::new("PriceTableNA_1774656000.zip")), Some(Region::Na));
+    assert_eq!(Region::from_file_name(Path::new("original_PriceTableEU.lua")), Some(Region::Eu));
+    assert_eq!(Region::from_file_name(Path::new("PriceTable_snapshot.lua")), None);
+    assert_eq!(Region::from_file_name(Path::new("final_prices.lua")), None);
+}
+
+#[test]
+fn renders_name_templates() {
+    let time = chrono::Utc.with_ymd_and_hms(2026, 3, 28, 9, 0, 0).unwrap();
+    assert_eq!(NameTemplate::default().render(Region::Na, &time), std::path::Path::new("2026/03/28/na"));
+    let template: NameTemplate = "{region}_{date}_{timestamp}".parse().unwrap();
+    assert_eq!(template.render(Region::Eu, &time), std::path::Path::new("eu_2026-03-28_1774688400"));
+    assert!("{date}".parse::<NameTemplate>().unwrap_err().contains("{region}"));
+    assert!("{region}_{hour}".parse::<NameTemplate>().unwrap_err().contains("unknown placeholder {hour}"));
+}
+
+#[test]
+fn skips_mentions_of_the_table_before_its_assignment() {
+    let table = price_table(&format!("[1]={{[0]={{[3]={{[-1]={{[\"0\"]={},}},}},}},}},", price_node(5.0)));
+    for preface in [
+        "-- self.PriceTable is built below\n",
+        "self.PriceTable = cached or {}\n",
+        "if self.PriceTable == nil then end\nlocal data = self.PriceTable.Data\n",
+    ] {
+        let lua = format!("{}{}", preface, table);
+        let entries = parse_ttc_lua(&lua).unwrap_or_else(|e| panic!("{:?}: {}", preface, e)).entries;
+        assert_eq!(entries.len(), 1, "{:?}", preface);
+        assert_eq!(entries[0].price.avg, 5.0);
+    }
+}
+
+#[test]
+fn formats_prices_without_float_noise() {
+    assert_eq!(format_price(0.3), "0.3");
+    assert_eq!(format_price(0.1 + 0.2), "0.3");
+    assert_eq!(format_price(7.4), "7.4");
+    assert_eq!(format_price(1_500_000.0), "1500000");
+    assert_eq!(format_price(1234.567891), "1234.5679");
+    assert_eq!(format_price(-0.00001), "0");
+}
+
+#[test]
+fn counts_tables_with_only_some_price_node_keys() {
+    let renamed = "{[\"Avg\"]=5,[\"X\"]=10,[\"N\"]=1,[\"EC\"]=2,[\"AC\"]=3,}";
+    let data = format!(
+        "[1]={{[1]={{[50]={{[-1]={{[\"0\"]={},}},}},}},}},\n[2]={{[1]={{[50]={{[-1]={{[\"0\"]={},}},}},}},}},\n",
+        price_node(4.0),
+        renamed
+    );
+    let mut ids = Vec::new();
+    let (_, stats) = for_each_ttc_entry(&price_table(&data), |e| ids.push(e.item_id)).unwrap();
+    assert_eq!(ids, ["1"]);
+    assert_eq!(stats.price_nodes, 1);
+    assert_eq!(stats.partial_price_nodes, 1);
+}
+
+#[test]
+fn parses_checked_in_fixture() {
+    let table = parse_ttc_lua(include_str!("fixtures/PriceTableNA.lua")).unwrap();
+    assert_eq!(table.len(), 9);
+    assert_eq!(table.timestamp, Some(1774656000));
+    assert_eq!(table.stats.nodes_skipped, 0);
+    assert_eq!(table.stats.partial_price_nodes, 0);
+
+    let find = |item_id: &str, quality: &str, trait_id: &str, variant: &str| {
+        table
+            .entries
+            .iter()
+            .find(|e| e.item_id == item_id && e.quality == quality && e.trait_id == trait_id && e.variant == variant)
+            .unwrap()
+    };
+    let wax = find("54173", "4", "-1", "0");
+    assert_eq!(wax.level, "1");
+    assert_eq!(wax.price.avg, 11243.58);
+    assert_eq!(wax.price.suggested_price, Some(11000.0));
+    assert_eq!(wax.price.sale_entry_count, Some(61));
+
+    let set_piece = find("139409", "5", "17", "6|21");
+    assert_eq!(set_piece.level, "66");
+    assert_eq!(set_piece.price.avg, 31000.25);
+    assert_eq!(set_piece.price.sale_avg, None);
+
+    let cheap = find("147300", "1", "-1", "0");
+    assert_eq!(cheap.price.min, 0.1);
+    assert_eq!(cheap.price.amount_count, 4400);
+}